@@ -0,0 +1,556 @@
+use std::mem;
+use std::ops::Range;
+
+use polytonic_greek::hgk_strip_diacritics;
+use rust_stemmers::{Algorithm, Stemmer};
+use tantivy::tokenizer::{
+    Language, LowerCaser, NgramTokenizer, PreTokenizedStream, PreTokenizedString, RawTokenizer,
+    SimpleTokenizer, TextAnalyzer, Token, TokenFilter, TokenStream, Tokenizer,
+};
+
+/// Name the definition analyzer is registered under; quotes use it too, since they may be in
+/// any language.
+pub const DEFINITION_TOKENIZER: &str = "definition";
+/// Name the analyzer for English text (glosses and translations) is registered under.
+pub const ENGLISH_TOKENIZER: &str = "en_stem";
+/// Name the analyzer for Greek text is registered under.
+pub const GREEK_TOKENIZER: &str = "grc";
+/// Name the analyzer for Latin text is registered under.
+pub const LATIN_TOKENIZER: &str = "la";
+/// Name the analyzer for whole lemmas without diacritics is registered under.
+pub const LEMMA_TOKENIZER: &str = "lemma_folded";
+/// Name the analyzer for the prefixes of lemmas without diacritics is registered under.
+pub const LEMMA_PREFIX_TOKENIZER: &str = "lemma_prefix";
+/// Longest lemma prefix indexed, in characters; longer prefixes are looked up by this many.
+pub const MAX_LEMMA_PREFIX: usize = 20;
+
+/// The language of some text in an entry.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TextLang {
+    English,
+    Greek,
+    Latin,
+}
+
+impl TextLang {
+    /// Reads a lang or xml:lang attribute value, e.g. "greek", "grc", "la" or "lat".
+    pub fn from_attr(value: &str) -> TextLang {
+        match value.to_lowercase().as_str() {
+            "greek" | "grc" | "gr" | "el" => TextLang::Greek,
+            "la" | "lat" | "latin" => TextLang::Latin,
+            _ => TextLang::English,
+        }
+    }
+}
+
+/// The text of an entry routed into the language-specific index fields.
+#[derive(Default)]
+pub struct FieldTexts {
+    /// English text outside quotes
+    pub gloss_en: FieldText,
+    pub text_grc: FieldText,
+    pub text_la: FieldText,
+    /// text in `<quote>`, in any language
+    pub quotes: FieldText,
+    /// text in `<i>`, the translations of LSJ
+    pub translations: FieldText,
+}
+
+impl FieldTexts {
+    /// Adds text found at byte offset `start` of the entry's plain text.
+    pub fn push(
+        &mut self,
+        start: usize,
+        text: &str,
+        lang: TextLang,
+        in_quote: bool,
+        in_translation: bool,
+    ) {
+        match lang {
+            TextLang::English if !in_quote => self.gloss_en.push(start, text),
+            TextLang::English => (),
+            TextLang::Greek => self.text_grc.push(start, text),
+            TextLang::Latin => self.text_la.push(start, text),
+        }
+        if in_quote {
+            self.quotes.push(start, text);
+        }
+        if in_translation {
+            self.translations.push(start, text);
+        }
+    }
+}
+
+/// Text gathered from parts of an entry, with a space between parts that were not adjacent so
+/// their words are not run together.
+#[derive(Default)]
+pub struct FieldText {
+    pub text: String,
+    end: usize,
+}
+
+impl FieldText {
+    fn push(&mut self, start: usize, text: &str) {
+        if !self.text.is_empty() && self.end != start {
+            self.text.push(' ');
+        }
+        self.text.push_str(text);
+        self.end = start + text.len();
+    }
+}
+
+/// Analyzer for definitions and queries on them: words are lowercased and stripped of
+/// diacritics, and only English (Latin-script) words are stemmed.
+pub fn definition_analyzer() -> TextAnalyzer {
+    TextAnalyzer::builder(SimpleTokenizer::default())
+        .filter(LowerCaser)
+        .filter(NoDiacritcs)
+        .filter(ScriptStemmer)
+        .build()
+}
+
+/// Analyzer for English text: stemmed, as the whole definition used to be.
+pub fn english_analyzer() -> TextAnalyzer {
+    TextAnalyzer::builder(SimpleTokenizer::default())
+        .filter(LowerCaser)
+        .filter(NoDiacritcs)
+        .filter(tantivy::tokenizer::Stemmer::new(Language::English))
+        .build()
+}
+
+/// Analyzer for Greek text: not stemmed, so a word only matches itself without diacritics.
+pub fn greek_analyzer() -> TextAnalyzer {
+    TextAnalyzer::builder(SimpleTokenizer::default())
+        .filter(LowerCaser)
+        .filter(NoDiacritcs)
+        .filter(MapFilter(fold_final_sigma))
+        .build()
+}
+
+/// Analyzer for Latin text: not stemmed, with `normalize_latin`'s spelling.
+pub fn latin_analyzer() -> TextAnalyzer {
+    TextAnalyzer::builder(SimpleTokenizer::default())
+        .filter(LowerCaser)
+        .filter(NoDiacritcs)
+        .filter(MapFilter(fold_latin_letters))
+        .build()
+}
+
+/// Analyzer for a lemma as a single term, lowercased and without diacritics, with final sigma
+/// written as σ and Latin j and v as i and u.
+pub fn lemma_analyzer() -> TextAnalyzer {
+    TextAnalyzer::builder(RawTokenizer::default())
+        .filter(LowerCaser)
+        .filter(NoDiacritcs)
+        .filter(MapFilter(fold_lemma))
+        .build()
+}
+
+/// Analyzer for the edge n-grams of a lemma (its prefixes up to `MAX_LEMMA_PREFIX` characters),
+/// normalized like `lemma_analyzer`. Only for indexing: a query is normalized with
+/// `lemma_analyzer` and looked up as a single prefix.
+pub fn lemma_prefix_analyzer() -> TextAnalyzer {
+    TextAnalyzer::builder(NgramTokenizer::prefix_only(1, MAX_LEMMA_PREFIX).unwrap())
+        .filter(LowerCaser)
+        .filter(NoDiacritcs)
+        .filter(MapFilter(fold_lemma))
+        .build()
+}
+
+/// Normalizes a lemma or a lookup of one with `lemma_analyzer`.
+pub fn normalize_lemma(lemma: &str) -> String {
+    let mut analyzer = lemma_analyzer();
+    let mut stream = analyzer.token_stream(lemma);
+    match stream.next() {
+        Some(token) => token.text.clone(),
+        None => String::new(),
+    }
+}
+
+/// Tokenizes a definition for the index.
+///
+/// Words in `latin_spans` (byte ranges of text marked as Latin in the xml) are indexed in their
+/// Latin normalized form instead of being stemmed as English. Queries on the definition are
+/// analyzed as English, so searches also look in `text_la` to find them.
+pub fn tokenize_definition(text: &str, latin_spans: &[Range<usize>]) -> PreTokenizedString {
+    let is_latin = |offset| latin_spans.iter().any(|span| span.contains(&offset));
+    PreTokenizedString {
+        text: text.to_string(),
+        tokens: definition_tokens(text, is_latin, false),
+    }
+}
+
+/// Analyzer for highlighting matches in a stored definition, whose Latin spans are not known:
+/// every Latin-script word gets both its English and its Latin normalized form, so it matches
+/// a term from either `definition` or `text_la`.
+pub fn highlight_analyzer() -> TextAnalyzer {
+    TextAnalyzer::from(HighlightTokenizer)
+}
+
+#[derive(Clone)]
+struct HighlightTokenizer;
+
+impl Tokenizer for HighlightTokenizer {
+    type TokenStream<'a> = PreTokenizedStream;
+
+    fn token_stream<'a>(&'a mut self, text: &'a str) -> Self::TokenStream<'a> {
+        PreTokenizedStream::from(PreTokenizedString {
+            text: text.to_string(),
+            tokens: definition_tokens(text, |_| true, true),
+        })
+    }
+}
+
+// the tokens of the definition analyzer, with the Latin normalized form of each Latin word in
+// place of its English form, or beside it at the same position if keep_english
+fn definition_tokens(
+    text: &str,
+    is_latin: impl Fn(usize) -> bool,
+    keep_english: bool,
+) -> Vec<Token> {
+    let mut analyzer = definition_analyzer();
+    let mut stream = analyzer.token_stream(text);
+    let mut tokens = Vec::new();
+    while let Some(token) = stream.next() {
+        let word = &text[token.offset_from..token.offset_to];
+        if !is_latin(token.offset_from) || is_greek(word) {
+            tokens.push(token.clone());
+            continue;
+        }
+        let normalized = normalize_latin(word);
+        if keep_english && normalized != token.text {
+            tokens.push(token.clone());
+        }
+        tokens.push(Token {
+            text: normalized,
+            ..token.clone()
+        });
+    }
+    tokens
+}
+
+/// Lowercases a Latin word, strips its diacritics (e.g. macrons) and spells it with i for j and
+/// u for v.
+pub fn normalize_latin(word: &str) -> String {
+    fold_latin_letters(&hgk_strip_diacritics(&word.to_lowercase(), 0xFFFFFFFF))
+}
+
+// spells a Latin word with i for j and u for v, so that e.g. "jubeo" and "iubeo" are found by the same lookup
+pub fn fold_latin_letters(word: &str) -> String {
+    word.chars()
+        .map(|c| match c {
+            'j' => 'i',
+            'J' => 'I',
+            'v' => 'u',
+            'V' => 'U',
+            _ => c,
+        })
+        .collect()
+}
+
+// writes final sigma as σ, so a word matches a query ending in σ
+fn fold_final_sigma(word: &str) -> String {
+    word.replace('ς', "σ")
+}
+
+fn fold_lemma(word: &str) -> String {
+    fold_latin_letters(&fold_final_sigma(word))
+}
+
+/// Whether a word has any Greek letters.
+pub fn is_greek(word: &str) -> bool {
+    word.chars()
+        .any(|c| matches!(c, '\u{0370}'..='\u{03FF}' | '\u{1F00}'..='\u{1FFF}'))
+}
+
+// Basic Latin through Latin Extended-B, and Latin Extended Additional
+fn is_latin_script(word: &str) -> bool {
+    word.chars()
+        .all(|c| matches!(c, '\u{0000}'..='\u{024F}' | '\u{1E00}'..='\u{1EFF}'))
+}
+
+/// Token filter that stems Latin-script words as English and leaves words in other scripts
+/// unstemmed, writing final sigma in Greek words as σ so it matches a query ending in σ.
+#[derive(Clone)]
+pub struct ScriptStemmer;
+
+impl TokenFilter for ScriptStemmer {
+    type Tokenizer<T: Tokenizer> = ScriptStemmerFilter<T>;
+
+    fn transform<T: Tokenizer>(self, tokenizer: T) -> Self::Tokenizer<T> {
+        ScriptStemmerFilter {
+            tokenizer,
+            buffer: String::new(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct ScriptStemmerFilter<T> {
+    tokenizer: T,
+    buffer: String,
+}
+
+impl<T: Tokenizer> Tokenizer for ScriptStemmerFilter<T> {
+    type TokenStream<'a> = ScriptStemmerTokenStream<'a, T::TokenStream<'a>>;
+
+    fn token_stream<'a>(&'a mut self, text: &'a str) -> Self::TokenStream<'a> {
+        self.buffer.clear();
+        ScriptStemmerTokenStream {
+            tail: self.tokenizer.token_stream(text),
+            stemmer: Stemmer::create(Algorithm::English),
+            buffer: &mut self.buffer,
+        }
+    }
+}
+
+pub struct ScriptStemmerTokenStream<'a, T> {
+    buffer: &'a mut String,
+    stemmer: Stemmer,
+    tail: T,
+}
+
+impl<T: TokenStream> TokenStream for ScriptStemmerTokenStream<'_, T> {
+    fn advance(&mut self) -> bool {
+        if !self.tail.advance() {
+            return false;
+        }
+        let text = &self.tail.token().text;
+        if is_greek(text) {
+            if text.contains('ς') {
+                *self.buffer = fold_final_sigma(text);
+                mem::swap(&mut self.tail.token_mut().text, self.buffer);
+            }
+            return true;
+        }
+        if !is_latin_script(text) {
+            return true;
+        }
+        self.buffer.clear();
+        self.buffer.push_str(&self.stemmer.stem(text));
+        mem::swap(&mut self.tail.token_mut().text, self.buffer);
+        true
+    }
+
+    fn token(&self) -> &Token {
+        self.tail.token()
+    }
+
+    fn token_mut(&mut self) -> &mut Token {
+        self.tail.token_mut()
+    }
+}
+
+/// Token filter that rewrites each term with a function.
+#[derive(Clone)]
+pub struct MapFilter(pub fn(&str) -> String);
+
+impl TokenFilter for MapFilter {
+    type Tokenizer<T: Tokenizer> = MapFilterWrapper<T>;
+
+    fn transform<T: Tokenizer>(self, tokenizer: T) -> Self::Tokenizer<T> {
+        MapFilterWrapper {
+            map: self.0,
+            tokenizer,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct MapFilterWrapper<T> {
+    map: fn(&str) -> String,
+    tokenizer: T,
+}
+
+impl<T: Tokenizer> Tokenizer for MapFilterWrapper<T> {
+    type TokenStream<'a> = MapTokenStream<T::TokenStream<'a>>;
+
+    fn token_stream<'a>(&'a mut self, text: &'a str) -> Self::TokenStream<'a> {
+        MapTokenStream {
+            map: self.map,
+            tail: self.tokenizer.token_stream(text),
+        }
+    }
+}
+
+pub struct MapTokenStream<T> {
+    map: fn(&str) -> String,
+    tail: T,
+}
+
+impl<T: TokenStream> TokenStream for MapTokenStream<T> {
+    fn advance(&mut self) -> bool {
+        if !self.tail.advance() {
+            return false;
+        }
+        let token = self.tail.token_mut();
+        token.text = (self.map)(&token.text);
+        true
+    }
+
+    fn token(&self) -> &Token {
+        self.tail.token()
+    }
+
+    fn token_mut(&mut self) -> &mut Token {
+        self.tail.token_mut()
+    }
+}
+
+/// Token filter that removes diacritics from terms.
+#[derive(Clone)]
+pub struct NoDiacritcs;
+
+impl TokenFilter for NoDiacritcs {
+    type Tokenizer<T: Tokenizer> = DiacriticFilter<T>;
+
+    fn transform<T: Tokenizer>(self, tokenizer: T) -> Self::Tokenizer<T> {
+        DiacriticFilter {
+            tokenizer,
+            buffer: String::new(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct DiacriticFilter<T> {
+    tokenizer: T,
+    buffer: String,
+}
+
+impl<T: Tokenizer> Tokenizer for DiacriticFilter<T> {
+    type TokenStream<'a> = DiacriticTokenStream<'a, T::TokenStream<'a>>;
+
+    fn token_stream<'a>(&'a mut self, text: &'a str) -> Self::TokenStream<'a> {
+        self.buffer.clear();
+        DiacriticTokenStream {
+            tail: self.tokenizer.token_stream(text),
+            buffer: &mut self.buffer,
+        }
+    }
+}
+
+pub struct DiacriticTokenStream<'a, T> {
+    buffer: &'a mut String,
+    tail: T,
+}
+
+// writes a lowercased version of text into output.
+fn to_diacritic_free_unicode(text: &str, output: &mut String) {
+    output.clear();
+    output.reserve(50);
+    // for c in text.chars() {
+    //     // Contrary to the std, we do not take care of sigma special case.
+    //     // This will have an normalizationo effect, which is ok for search.
+    //     output.extend(c.to_lowercase());
+    // }
+    let stripped = hgk_strip_diacritics(text, 0xFFFFFFFF);
+    output.push_str(&stripped);
+}
+
+impl<T: TokenStream> TokenStream for DiacriticTokenStream<'_, T> {
+    fn advance(&mut self) -> bool {
+        if !self.tail.advance() {
+            return false;
+        }
+        // if self.token_mut().text.is_ascii() {
+        //     // fast track for ascii.
+        //     self.token_mut().text.make_ascii_lowercase();
+        // } else {
+        to_diacritic_free_unicode(&self.tail.token().text, self.buffer);
+        mem::swap(&mut self.tail.token_mut().text, self.buffer);
+        //}
+        true
+    }
+
+    fn token(&self) -> &Token {
+        self.tail.token()
+    }
+
+    fn token_mut(&mut self) -> &mut Token {
+        self.tail.token_mut()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(tokens: &[Token]) -> Vec<(&str, usize)> {
+        tokens
+            .iter()
+            .map(|t| (t.text.as_str(), t.position))
+            .collect()
+    }
+
+    fn analyze(analyzer: &mut TextAnalyzer, text: &str) -> Vec<String> {
+        let mut stream = analyzer.token_stream(text);
+        let mut terms = Vec::new();
+        while let Some(token) = stream.next() {
+            terms.push(token.text.clone());
+        }
+        terms
+    }
+
+    #[test]
+    fn script_stemmer_only_stems_latin_script() {
+        let mut analyzer = definition_analyzer();
+        assert_eq!(
+            analyze(&mut analyzer, "Carrying λογος loosed"),
+            ["carri", "λογοσ", "loos"]
+        );
+    }
+
+    #[test]
+    fn latin_spans_are_normalized_not_stemmed() {
+        let text = "to carry, Lat. jubeo vivus";
+        let span = text.find("jubeo").unwrap()..text.len();
+        let tokens = tokenize_definition(text, std::slice::from_ref(&span)).tokens;
+        assert_eq!(
+            texts(&tokens),
+            [
+                ("to", 0),
+                ("carri", 1),
+                ("lat", 2),
+                ("iubeo", 3),
+                ("uiuus", 4)
+            ]
+        );
+        let word = &tokens[3];
+        assert_eq!(&text[word.offset_from..word.offset_to], "jubeo");
+    }
+
+    #[test]
+    fn greek_in_latin_spans_is_left_alone() {
+        let text = "λογος jubeo";
+        let tokens = tokenize_definition(text, std::slice::from_ref(&(0..text.len()))).tokens;
+        assert_eq!(texts(&tokens), [("λογοσ", 0), ("iubeo", 1)]);
+    }
+
+    #[test]
+    fn highlighting_gives_english_and_latin_forms() {
+        let mut analyzer = highlight_analyzer();
+        assert_eq!(
+            analyze(&mut analyzer, "jubeo carrying"),
+            ["jubeo", "iubeo", "carri", "carrying"]
+        );
+    }
+
+    #[test]
+    fn latin_normalization() {
+        assert_eq!(normalize_latin("Jubeo"), "iubeo");
+        assert_eq!(normalize_latin("VIVUS"), "uiuus");
+        assert_eq!(normalize_latin("amicus"), "amicus");
+    }
+
+    #[test]
+    fn lemmas_are_folded_and_prefixed() {
+        assert_eq!(normalize_lemma("Λογος"), "λογοσ");
+        assert_eq!(normalize_lemma("Jubeo"), "iubeo");
+        let mut analyzer = lemma_prefix_analyzer();
+        assert_eq!(
+            analyze(&mut analyzer, "λογος"),
+            ["λ", "λο", "λογ", "λογο", "λογοσ"]
+        );
+    }
+}
@@ -0,0 +1,115 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use anyhow::Context;
+use serde::Deserialize;
+
+/// An author in a lexicon's list of abbreviations.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Author {
+    /// full name, e.g. "Homer"
+    pub name: String,
+    /// TLG or PHI author id, e.g. "tlg0012"
+    pub id: Option<String>,
+}
+
+// what an abbreviation stands for: one author, or several told apart by the citation's author id
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Expansion {
+    One(Author),
+    Several(Vec<Author>),
+}
+
+/// A lexicon's author abbreviations, e.g. "Hom." -> Homer, read from a json object of
+/// abbreviation -> { "name": ..., "id": ... }. An abbreviation used for more than one author, such
+/// as "Plin." for both Plinys, maps to a list of them instead.
+#[derive(Default)]
+pub struct AuthorList {
+    authors: HashMap<String, Expansion>,
+}
+
+impl AuthorList {
+    pub fn load(path: Option<&Path>) -> anyhow::Result<AuthorList> {
+        let Some(path) = path else {
+            return Ok(AuthorList::default());
+        };
+        let json = fs::read_to_string(path)
+            .with_context(|| format!("could not read author list {}", path.display()))?;
+        let authors: HashMap<String, Expansion> = serde_json::from_str(&json)
+            .with_context(|| format!("could not parse author list {}", path.display()))?;
+        Ok(AuthorList {
+            authors: authors
+                .into_iter()
+                .map(|(abbrev, author)| (normalize(&abbrev), author))
+                .collect(),
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.authors.is_empty()
+    }
+
+    /// The author an abbreviation stands for. One of several authors sharing an abbreviation is
+    /// only picked by `cited_id`, the author id of the reference the abbreviation is in.
+    pub fn get(&self, abbrev: &str, cited_id: Option<&str>) -> Option<&Author> {
+        match self.authors.get(&normalize(abbrev))? {
+            Expansion::One(author) => Some(author),
+            Expansion::Several(authors) => authors
+                .iter()
+                .find(|author| cited_id.is_some() && author.id.as_deref() == cited_id),
+        }
+    }
+}
+
+/// An `<author>` in an entry, with its expansion if the lexicon's author list has one.
+#[derive(Debug, Default)]
+pub struct AuthorRef {
+    pub abbrev: String,
+    pub author: Option<Author>,
+}
+
+fn normalize(abbrev: &str) -> String {
+    abbrev.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shared_abbreviations_go_by_the_cited_author() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("authors.json");
+        fs::write(
+            &path,
+            r#"{
+                "Cic.": { "name": "Cicero", "id": "phi0474" },
+                "Plin.": [
+                    { "name": "Pliny the Elder", "id": "phi0978" },
+                    { "name": "Pliny the Younger", "id": "phi1318" }
+                ]
+            }"#,
+        )
+        .unwrap();
+        let authors = AuthorList::load(Some(&path)).unwrap();
+        let name = |abbrev, cited_id| authors.get(abbrev, cited_id).map(|a| a.name.as_str());
+        assert_eq!(name("Cic.", None), Some("Cicero"));
+        assert_eq!(name(" Cic. ", Some("phi0978")), Some("Cicero"));
+        assert_eq!(name("Plin.", Some("phi1318")), Some("Pliny the Younger"));
+        assert_eq!(name("Plin.", Some("phi0978")), Some("Pliny the Elder"));
+        assert_eq!(name("Plin.", Some("phi0474")), None);
+        assert_eq!(name("Plin.", None), None);
+        assert_eq!(name("Hom.", None), None);
+    }
+
+    #[test]
+    fn shipped_lists_load() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("authors");
+        for list in ["lsj.json", "lewisshort.json"] {
+            let authors = AuthorList::load(Some(&dir.join(list))).unwrap();
+            assert!(!authors.is_empty(), "{list}");
+        }
+    }
+}
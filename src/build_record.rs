@@ -0,0 +1,91 @@
+use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use sqlx::AnyConnection;
+use tantivy::Index;
+
+use crate::dialect::Dialect;
+use crate::migrations::{get_meta, set_meta};
+
+/// What a db or index was built from: the loader version, the lexica loaded, and the commit of
+/// each lexicon's repository where it is a git repository. It is kept in the db's meta table and
+/// in the payload of the index's last commit.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct BuildRecord {
+    pub loader_version: String,
+    pub lexica: Vec<String>,
+    pub commits: BTreeMap<String, String>,
+}
+
+impl BuildRecord {
+    pub fn new() -> BuildRecord {
+        BuildRecord {
+            loader_version: env!("CARGO_PKG_VERSION").to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn add(&mut self, lexicon: &str, commit: Option<String>) {
+        self.lexica.push(lexicon.to_string());
+        if let Some(commit) = commit {
+            self.commits.insert(lexicon.to_string(), commit);
+        }
+    }
+
+    /// True if this loader wrote the build, so that rows it loaded can be kept.
+    pub fn is_current(&self) -> bool {
+        self.loader_version == env!("CARGO_PKG_VERSION")
+    }
+
+    pub async fn read_db(db: &mut AnyConnection) -> anyhow::Result<BuildRecord> {
+        let loader_version = get_meta(db, "loader_version").await?.unwrap_or_default();
+        let lexica = get_meta(db, "lexica").await?.unwrap_or_default();
+        let query =
+            Dialect::of(db).sql(r#"SELECT "key", value FROM meta WHERE "key" LIKE 'commit.%';"#);
+        let rows: Vec<(String, String)> = sqlx::query_as(&query).fetch_all(&mut *db).await?;
+        Ok(BuildRecord {
+            loader_version,
+            lexica: lexica
+                .split(',')
+                .filter(|s| !s.is_empty())
+                .map(String::from)
+                .collect(),
+            commits: rows
+                .into_iter()
+                .map(|(key, value)| (key.trim_start_matches("commit.").to_string(), value))
+                .collect(),
+        })
+    }
+
+    /// Writes the record to the meta table, with the time of the build in built_at and each
+    /// commit under e.g. "commit.lsj".
+    pub async fn write_db(&self, db: &mut AnyConnection) -> anyhow::Result<()> {
+        let built_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        set_meta(db, "loader_version", &self.loader_version).await?;
+        set_meta(db, "built_at", &built_at.to_string()).await?;
+        set_meta(db, "lexica", &self.lexica.join(",")).await?;
+        sqlx::query(&Dialect::of(db).sql(r#"DELETE FROM meta WHERE "key" LIKE 'commit.%';"#))
+            .execute(&mut *db)
+            .await?;
+        for (lexicon, commit) in &self.commits {
+            set_meta(db, &format!("commit.{}", lexicon), commit).await?;
+        }
+        Ok(())
+    }
+
+    /// The record in the payload of the index's last commit, or an empty one if it has none.
+    pub fn read_index(index: &Index) -> anyhow::Result<BuildRecord> {
+        match index.load_metas()?.payload {
+            Some(payload) => Ok(serde_json::from_str(&payload).unwrap_or_default()),
+            None => Ok(BuildRecord::default()),
+        }
+    }
+
+    pub fn to_payload(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+}
@@ -0,0 +1,212 @@
+/// A `<bibl>` reference in an entry.
+#[derive(Debug)]
+pub struct Citation {
+    /// the bibl's n attribute, e.g. "Perseus:abo:tlg,0012,001:1:1"
+    pub n: String,
+    /// the text of the bibl as displayed in the entry, e.g. "Hom. Il. 1.1"
+    pub text: String,
+    /// path of the sense the bibl is in, e.g. "A.II.2", or empty if it is not in a sense
+    pub sense: String,
+    /// n parsed into its CTS components, if it could be
+    pub cts: Option<CtsRef>,
+}
+
+impl Citation {
+    pub fn new(n: &str, sense: &str) -> Self {
+        Self {
+            n: n.to_string(),
+            text: String::new(),
+            sense: sense.to_string(),
+            cts: CtsRef::parse(n),
+        }
+    }
+}
+
+/// The author, work and passage of a Perseus style reference.
+#[derive(Debug, PartialEq)]
+pub struct CtsRef {
+    /// CTS namespace, "greekLit" or "latinLit"
+    pub namespace: &'static str,
+    /// text group, e.g. "tlg0012"
+    pub author: String,
+    /// work within the text group, e.g. "tlg001"
+    pub work: Option<String>,
+    /// passage with levels separated by ".", e.g. "1.1"
+    pub passage: Option<String>,
+}
+
+impl CtsRef {
+    /// Parses a reference like "Perseus:abo:tlg,0012,001:1:1" (an abbreviated tlg, phi or stoa
+    /// reference to an author, an optional work and an optional passage).
+    pub fn parse(n: &str) -> Option<CtsRef> {
+        let mut parts = n.trim().split(':');
+        if parts.next()? != "Perseus" || parts.next()? != "abo" {
+            return None;
+        }
+
+        let mut ids = parts.next()?.split(',');
+        let collection = ids.next()?;
+        let namespace = match collection {
+            "tlg" => "greekLit",
+            "phi" | "stoa" => "latinLit",
+            _ => return None,
+        };
+        let is_id = |s: &str| !s.is_empty() && s.chars().all(|c| c.is_ascii_alphanumeric());
+        let author = ids.next().filter(|s| is_id(s))?;
+        let work = match ids.next() {
+            Some(work) if is_id(work) => Some(format!("{}{}", collection, work)),
+            Some(_) => return None,
+            None => None,
+        };
+        if ids.next().is_some() {
+            return None;
+        }
+
+        let levels: Vec<&str> = parts.collect();
+        if levels
+            .iter()
+            .any(|l| l.is_empty() || l.contains(char::is_whitespace))
+        {
+            return None;
+        }
+        let passage = if levels.is_empty() {
+            None
+        } else {
+            Some(levels.join("."))
+        };
+        if passage.is_some() && work.is_none() {
+            return None;
+        }
+
+        Some(CtsRef {
+            namespace,
+            author: format!("{}{}", collection, author),
+            work,
+            passage,
+        })
+    }
+
+    /// The urn, author, work and passage, as attributes of the bibl for render rules to use.
+    pub fn attributes(&self) -> Vec<(String, String)> {
+        let mut attrs = vec![
+            ("urn".to_string(), self.urn()),
+            ("author".to_string(), self.author.clone()),
+        ];
+        if let Some(work) = &self.work {
+            attrs.push(("work".to_string(), work.clone()));
+        }
+        if let Some(passage) = &self.passage {
+            attrs.push(("passage".to_string(), passage.clone()));
+        }
+        attrs
+    }
+
+    /// e.g. "urn:cts:greekLit:tlg0012.tlg001:1.1"
+    pub fn urn(&self) -> String {
+        let mut urn = format!("urn:cts:{}:{}", self.namespace, self.author);
+        if let Some(work) = &self.work {
+            urn.push('.');
+            urn.push_str(work);
+        }
+        if let Some(passage) = &self.passage {
+            urn.push(':');
+            urn.push_str(passage);
+        }
+        urn
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn urn(n: &str) -> Option<String> {
+        CtsRef::parse(n).map(|cts| cts.urn())
+    }
+
+    #[test]
+    fn collections() {
+        assert_eq!(
+            CtsRef::parse("Perseus:abo:tlg,0012,001:1:1"),
+            Some(CtsRef {
+                namespace: "greekLit",
+                author: "tlg0012".to_string(),
+                work: Some("tlg001".to_string()),
+                passage: Some("1.1".to_string()),
+            })
+        );
+        assert_eq!(
+            urn("Perseus:abo:phi,0474,043:2:5"),
+            Some("urn:cts:latinLit:phi0474.phi043:2.5".to_string())
+        );
+        assert_eq!(
+            urn("Perseus:abo:stoa,0275,001:3"),
+            Some("urn:cts:latinLit:stoa0275.stoa001:3".to_string())
+        );
+        assert_eq!(urn("Perseus:abo:xyz,0012,001:1"), None);
+    }
+
+    #[test]
+    fn author_and_work_only() {
+        assert_eq!(
+            urn("Perseus:abo:tlg,0059"),
+            Some("urn:cts:greekLit:tlg0059".to_string())
+        );
+        assert_eq!(
+            urn("Perseus:abo:tlg,0059,030"),
+            Some("urn:cts:greekLit:tlg0059.tlg030".to_string())
+        );
+    }
+
+    #[test]
+    fn passage_levels() {
+        assert_eq!(
+            urn("Perseus:abo:tlg,0059,030:327c"),
+            Some("urn:cts:greekLit:tlg0059.tlg030:327c".to_string())
+        );
+        assert_eq!(
+            urn("Perseus:abo:tlg,0003,001:2:65:3"),
+            Some("urn:cts:greekLit:tlg0003.tlg001:2.65.3".to_string())
+        );
+        // surrounding whitespace is ignored
+        assert_eq!(
+            urn(" Perseus:abo:tlg,0012,001:1:1\n"),
+            Some("urn:cts:greekLit:tlg0012.tlg001:1.1".to_string())
+        );
+    }
+
+    #[test]
+    fn attributes_for_render_rules() {
+        let cts = CtsRef::parse("Perseus:abo:tlg,0012,001:1:1").unwrap();
+        let attrs = cts.attributes();
+        let attrs: Vec<(&str, &str)> = attrs
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+            .collect();
+        assert_eq!(
+            attrs,
+            [
+                ("urn", "urn:cts:greekLit:tlg0012.tlg001:1.1"),
+                ("author", "tlg0012"),
+                ("work", "tlg001"),
+                ("passage", "1.1"),
+            ]
+        );
+        let cts = CtsRef::parse("Perseus:abo:tlg,0012").unwrap();
+        assert_eq!(cts.attributes().len(), 2);
+    }
+
+    #[test]
+    fn rejects_malformed_references() {
+        // a passage needs a work
+        assert_eq!(CtsRef::parse("Perseus:abo:tlg,0012:1:1"), None);
+        assert_eq!(CtsRef::parse("Perseus:abo:tlg,0012,001:1 :1"), None);
+        assert_eq!(CtsRef::parse("Perseus:abo:tlg,0012,001:1::1"), None);
+        assert_eq!(CtsRef::parse("Perseus:abo:tlg,00 12,001"), None);
+        assert_eq!(CtsRef::parse("Perseus:abo:tlg,0012,001,1:1"), None);
+        assert_eq!(CtsRef::parse("Perseus:abo:tlg,0012,:1"), None);
+        assert_eq!(CtsRef::parse("Perseus:abo:tlg"), None);
+        assert_eq!(CtsRef::parse("Perseus:text:1999.04.0057"), None);
+        assert_eq!(CtsRef::parse(""), None);
+    }
+}
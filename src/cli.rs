@@ -0,0 +1,196 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, bail};
+
+use crate::config::DEFAULT_REGISTRY;
+use crate::search::SearchMode;
+
+pub static USAGE: &str = "\
+usage: tantivy-test [options] <command> [args]
+
+commands:
+  build           fetch repositories, then rebuild the db and index (default)
+  fetch           clone or pull the lexicon repositories only
+  load            rebuild the db only
+  index           rebuild the tantivy index only
+  search <query>  run a query against an existing index
+  verify          check that an existing db and index agree
+  bench           compare index size and build time with and without positions, per lexicon
+  rollback        put back the db and index from before the last build
+
+options:
+  --registry <path>     lexicon registry (default: lexica.json)
+  --db <path>           sqlite db (default: dbv3.sqlite)
+  --db-url <url>        database url, e.g. postgres://user@host/lexica, used instead of --db
+  --index <path>        tantivy index directory (default: tantivy-datav4)
+  --lexicon <names>     comma separated lexica to process or search (default: all enabled)
+  --limit <n>           maximum number of search results (default: 100)
+  --offset <n>          number of search results to skip, for paging (default: 0)
+  --json                print search results as json instead of a table
+  --prefix              search for lemmas starting with the query, ignoring diacritics
+  --fuzzy               search for lemmas within one or two edits of the query, ignoring diacritics
+  --max-errors <n>      source errors to allow before failing the build (default: 0)
+  --no-pull             do not fetch repositories before loading
+  --full                reload every lexicon, even those whose repository has not changed
+  --no-positions        do not index positions in definitions, which phrase queries need
+  -h, --help            print this message";
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Build,
+    Fetch,
+    Load,
+    Index,
+    Search { query: String },
+    Verify,
+    Bench,
+    Rollback,
+    Help,
+}
+
+#[derive(Debug)]
+pub struct Args {
+    pub command: Command,
+    pub registry: PathBuf,
+    pub db_path: PathBuf,
+    pub db_url: Option<String>, // sqlx url of a sqlite, postgres or mysql database
+    pub index_path: PathBuf,
+    pub lexica: Vec<String>,
+    pub limit: usize,
+    pub offset: usize,
+    pub search_mode: SearchMode,
+    pub json: bool,
+    pub max_errors: usize,
+    pub pull: bool,
+    pub full: bool,
+    pub positions: bool, // index positions in definitions
+}
+
+impl Args {
+    pub fn parse() -> anyhow::Result<Args> {
+        Self::parse_from(std::env::args().skip(1))
+    }
+
+    pub fn parse_from<I: IntoIterator<Item = String>>(args: I) -> anyhow::Result<Args> {
+        let mut registry = PathBuf::from(DEFAULT_REGISTRY);
+        let mut db_path = PathBuf::from("dbv3.sqlite");
+        let mut db_url = None;
+        let mut index_path = PathBuf::from("tantivy-datav4");
+        let mut lexica = Vec::new();
+        let mut limit = 100;
+        let mut offset = 0;
+        let mut search_mode = SearchMode::Query;
+        let mut json = false;
+        let mut max_errors = 0;
+        let mut pull = true;
+        let mut full = false;
+        let mut positions = true;
+        let mut help = false;
+        let mut positional = Vec::new();
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| {
+                args.next()
+                    .with_context(|| format!("missing value for {}", name))
+            };
+            match arg.as_str() {
+                "-h" | "--help" => help = true,
+                "--registry" => registry = PathBuf::from(value(&arg)?),
+                "--db" => db_path = PathBuf::from(value(&arg)?),
+                "--db-url" => db_url = Some(value(&arg)?),
+                "--index" => index_path = PathBuf::from(value(&arg)?),
+                "--lexicon" => lexica.extend(
+                    value(&arg)?
+                        .split(',')
+                        .filter(|s| !s.is_empty())
+                        .map(String::from),
+                ),
+                "--limit" => {
+                    limit = value(&arg)?
+                        .parse()
+                        .context("--limit must be a positive number")?;
+                    if limit == 0 {
+                        bail!("--limit must be a positive number");
+                    }
+                }
+                "--offset" => offset = value(&arg)?.parse().context("--offset must be a number")?,
+                "--json" => json = true,
+                "--prefix" | "--fuzzy" => {
+                    if search_mode != SearchMode::Query {
+                        bail!("--prefix and --fuzzy cannot be used together");
+                    }
+                    search_mode = if arg == "--prefix" {
+                        SearchMode::Prefix
+                    } else {
+                        SearchMode::Fuzzy
+                    };
+                }
+                "--max-errors" => {
+                    max_errors = value(&arg)?
+                        .parse()
+                        .context("--max-errors must be a number")?
+                }
+                "--no-pull" => pull = false,
+                "--full" => full = true,
+                "--no-positions" => positions = false,
+                s if s.starts_with('-') => bail!("unknown option {}", s),
+                _ => positional.push(arg),
+            }
+        }
+
+        let mut positional = positional.into_iter();
+        let command = match positional.next().as_deref() {
+            _ if help => Command::Help,
+            None | Some("build") => Command::Build,
+            Some("fetch") => Command::Fetch,
+            Some("load") => Command::Load,
+            Some("index") => Command::Index,
+            Some("verify") => Command::Verify,
+            Some("bench") => Command::Bench,
+            Some("rollback") => Command::Rollback,
+            Some("help") => Command::Help,
+            Some("search") => {
+                let query = positional.by_ref().collect::<Vec<_>>().join(" ");
+                if query.trim().is_empty() {
+                    bail!("search requires a query");
+                }
+                Command::Search { query }
+            }
+            Some(s) => bail!("unknown command {}", s),
+        };
+        if let Some(extra) = positional.next()
+            && command != Command::Help
+        {
+            bail!("unexpected argument {}", extra);
+        }
+
+        Ok(Args {
+            command,
+            registry,
+            db_path,
+            db_url,
+            index_path,
+            lexica,
+            limit,
+            offset,
+            search_mode,
+            json,
+            max_errors,
+            pull,
+            full,
+            positions,
+        })
+    }
+
+    pub fn db_url(&self) -> String {
+        match &self.db_url {
+            Some(url) => url.clone(),
+            None => sqlite_url(&self.db_path),
+        }
+    }
+}
+
+pub fn sqlite_url(path: &Path) -> String {
+    format!("sqlite://{}?mode=rwc", path.display())
+}
@@ -0,0 +1,157 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, bail};
+use serde::Deserialize;
+
+pub static DEFAULT_REGISTRY: &str = "lexica.json";
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Lexicon {
+    pub name: String,
+    pub display_name: String,
+    pub repo_url: String,
+    #[serde(default = "default_branch")]
+    pub branch: String,
+    #[serde(default = "default_remote")]
+    pub remote: String,
+    pub dir_name: String,
+    pub file_glob: Option<String>, // e.g. "greatscott*.xml", matched within dir_name
+    #[serde(default)]
+    pub file_exclude: Vec<String>, // file names matched by file_glob to skip
+    #[serde(default)]
+    pub expected_gaps: Vec<String>, // numbered file names file_glob is known to lack
+    pub file_manifest: Option<Vec<String>>, // explicit ordered list of files in dir_name
+    #[serde(default)]
+    pub missing_files: MissingFiles,
+    pub render_rules: Option<PathBuf>, // json list of render rules used before the defaults
+    pub authors: Option<PathBuf>,      // json object of author abbreviations
+    #[serde(default)]
+    pub explicit_homographs: bool, // number homographs by n on <head> or <orth> where present
+    #[serde(default = "default_true")]
+    pub pull: bool,
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+}
+
+/// What to do when a manifest file is missing or a glob matches nothing.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum MissingFiles {
+    #[default]
+    Error,
+    Warn,
+}
+
+fn default_branch() -> String {
+    String::from("master")
+}
+
+fn default_remote() -> String {
+    String::from("origin")
+}
+
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Debug, Deserialize)]
+struct Registry {
+    lexica: Vec<Lexicon>,
+}
+
+impl Lexicon {
+    fn validate(&self) -> anyhow::Result<()> {
+        if self.name.trim().is_empty() {
+            bail!("lexicon name must not be empty");
+        }
+        if !self
+            .name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            bail!(
+                "lexicon name \"{}\" may only contain ascii letters, digits, '-' and '_'",
+                self.name
+            );
+        }
+        if self.repo_url.trim().is_empty() {
+            bail!("lexicon \"{}\" is missing repo_url", self.name);
+        }
+        if self.dir_name.trim().is_empty() {
+            bail!("lexicon \"{}\" is missing dir_name", self.name);
+        }
+        match (&self.file_glob, &self.file_manifest) {
+            (Some(_), Some(_)) => bail!(
+                "lexicon \"{}\" must have either file_glob or file_manifest, not both",
+                self.name
+            ),
+            (None, None) => bail!(
+                "lexicon \"{}\" is missing file_glob or file_manifest",
+                self.name
+            ),
+            (Some(glob), None) if glob.trim().is_empty() || glob.contains('/') => bail!(
+                "lexicon \"{}\" file_glob must be a non-empty file name pattern",
+                self.name
+            ),
+            (None, Some(manifest)) if manifest.is_empty() => {
+                bail!("lexicon \"{}\" has an empty file_manifest", self.name)
+            }
+            _ => (),
+        }
+        if !self.file_exclude.is_empty() && self.file_glob.is_none() {
+            bail!(
+                "lexicon \"{}\" file_exclude only applies to file_glob",
+                self.name
+            );
+        }
+        if !self.expected_gaps.is_empty() && self.file_glob.is_none() {
+            bail!(
+                "lexicon \"{}\" expected_gaps only applies to file_glob",
+                self.name
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Reads and validates the lexicon registry at `path`, returning every lexicon in file order.
+pub fn load_lexica(path: &Path) -> anyhow::Result<Vec<Lexicon>> {
+    let json = fs::read_to_string(path)
+        .with_context(|| format!("could not read lexicon registry {}", path.display()))?;
+    let registry: Registry = serde_json::from_str(&json)
+        .with_context(|| format!("could not parse lexicon registry {}", path.display()))?;
+
+    let mut names = HashSet::new();
+    for lex in &registry.lexica {
+        lex.validate()
+            .with_context(|| format!("invalid lexicon registry {}", path.display()))?;
+        if !names.insert(lex.name.as_str()) {
+            bail!(
+                "invalid lexicon registry {}: lexicon \"{}\" is listed more than once",
+                path.display(),
+                lex.name
+            );
+        }
+    }
+
+    Ok(registry.lexica)
+}
+
+/// Picks the lexica to process: the enabled ones if `names` is empty, otherwise
+/// exactly those named (enabled or not), in registry order.
+pub fn select_lexica(lexica: Vec<Lexicon>, names: &[String]) -> anyhow::Result<Vec<Lexicon>> {
+    if names.is_empty() {
+        return Ok(lexica.into_iter().filter(|l| l.enabled).collect());
+    }
+    for name in names {
+        if !lexica.iter().any(|l| &l.name == name) {
+            bail!("unknown lexicon \"{}\"", name);
+        }
+    }
+    Ok(lexica
+        .into_iter()
+        .filter(|l| names.contains(&l.name))
+        .collect())
+}
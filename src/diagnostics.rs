@@ -0,0 +1,94 @@
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+
+use crate::sources::LineIndex;
+
+/// A problem found while reading a source file. Unless the message says how it was dealt with,
+/// the entry it occurred in is skipped.
+pub struct Diagnostic {
+    pub file: PathBuf,
+    pub offset: u64,
+    pub line: usize,
+    pub column: usize,
+    pub entry_id: String,
+    pub message: String,
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}:{}:{} (byte {})",
+            self.file.display(),
+            self.line,
+            self.column,
+            self.offset
+        )?;
+        if !self.entry_id.is_empty() {
+            write!(f, " in entry {}", self.entry_id)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+/// Collects the errors from a build so it can carry on past them and report them all at the end.
+pub struct Diagnostics {
+    errors: Vec<Diagnostic>,
+    max_errors: usize,
+}
+
+impl Diagnostics {
+    pub fn new(max_errors: usize) -> Self {
+        Self {
+            errors: Vec::new(),
+            max_errors,
+        }
+    }
+
+    /// Records an error at a byte offset of a file, whose line and column are found with
+    /// `lines` if the file could be read.
+    pub fn error(
+        &mut self,
+        file: &Path,
+        lines: Option<&LineIndex>,
+        offset: u64,
+        entry_id: &str,
+        message: impl Display,
+    ) {
+        let (line, column) = lines.map_or((0, 0), |lines| lines.line_col(offset));
+        let diagnostic = Diagnostic {
+            file: file.to_path_buf(),
+            offset,
+            line,
+            column,
+            entry_id: entry_id.to_string(),
+            message: message.to_string(),
+        };
+        eprintln!("error: {}", diagnostic);
+        self.errors.push(diagnostic);
+    }
+
+    pub fn count(&self) -> usize {
+        self.errors.len()
+    }
+
+    /// True if there were more errors than the build allows.
+    pub fn exceeded(&self) -> bool {
+        self.errors.len() > self.max_errors
+    }
+
+    pub fn print_summary(&self) {
+        if self.errors.is_empty() {
+            println!("no errors");
+            return;
+        }
+        println!(
+            "{} error(s), {} allowed:",
+            self.errors.len(),
+            self.max_errors
+        );
+        for diagnostic in &self.errors {
+            println!("  {}", diagnostic);
+        }
+    }
+}
@@ -0,0 +1,109 @@
+use std::borrow::Cow;
+
+use sqlx::AnyConnection;
+
+/// The SQL differences between the databases the loader can write to.
+///
+/// Queries are written for SQLite and PostgreSQL: `$1`-style placeholders, each used once and in
+/// order, and identifiers that need quoting in double quotes. DDL can also use `{strict}` for
+/// SQLite's STRICT tables, `{text}` for a column that may hold a whole entry, and `{key}` for a
+/// text column that is part of a key or index.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Dialect {
+    Sqlite,
+    Postgres,
+    MySql,
+}
+
+impl Dialect {
+    pub fn of(db: &AnyConnection) -> Dialect {
+        match db.backend_name() {
+            "PostgreSQL" => Dialect::Postgres,
+            "MySQL" => Dialect::MySql,
+            _ => Dialect::Sqlite,
+        }
+    }
+
+    /// Rewrites a query for this database.
+    pub fn sql<'a>(&self, query: &'a str) -> Cow<'a, str> {
+        match self {
+            Dialect::Sqlite | Dialect::Postgres => Cow::Borrowed(query),
+            Dialect::MySql => {
+                let mut sql = String::with_capacity(query.len());
+                let mut chars = query.chars().peekable();
+                while let Some(c) = chars.next() {
+                    match c {
+                        '$' if chars.peek().is_some_and(char::is_ascii_digit) => {
+                            while chars.next_if(char::is_ascii_digit).is_some() {}
+                            sql.push('?');
+                        }
+                        '"' => sql.push('`'),
+                        _ => sql.push(c),
+                    }
+                }
+                Cow::Owned(sql)
+            }
+        }
+    }
+
+    /// Fills in the DDL placeholders and rewrites the statement for this database.
+    pub fn ddl(&self, statement: &str) -> String {
+        let (strict, text, key) = match self {
+            Dialect::Sqlite => (" STRICT", "TEXT", "TEXT"),
+            Dialect::Postgres => ("", "TEXT", "TEXT"),
+            // MySQL can only index text columns of a fixed length, and TEXT holds just 64KB; keys
+            // are compared byte by byte as in the others, not ignoring case and accents
+            Dialect::MySql => ("", "LONGTEXT", "VARCHAR(255) COLLATE utf8mb4_bin"),
+        };
+        let statement = statement
+            .replace("{strict}", strict)
+            .replace("{text}", text)
+            .replace("{key}", key);
+        self.sql(&statement).into_owned()
+    }
+
+    /// Statement to reclaim space and update statistics after a load, if the database has one
+    /// that can run outside a transaction.
+    pub fn vacuum(&self) -> Option<&'static str> {
+        match self {
+            Dialect::Sqlite => Some("VACUUM;"),
+            Dialect::Postgres => Some("VACUUM ANALYZE;"),
+            Dialect::MySql => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mysql_gets_question_marks_and_backticks() {
+        let query = r#"SELECT "key", value FROM meta WHERE "key" = $1 AND seq IN ($2, $10);"#;
+        assert_eq!(Dialect::Sqlite.sql(query), query);
+        assert_eq!(Dialect::Postgres.sql(query), query);
+        assert_eq!(
+            Dialect::MySql.sql(query),
+            "SELECT `key`, value FROM meta WHERE `key` = ? AND seq IN (?, ?);"
+        );
+        // a dollar sign not followed by a digit is left alone
+        assert_eq!(Dialect::MySql.sql("SELECT '$a', $;"), "SELECT '$a', $;");
+    }
+
+    #[test]
+    fn ddl_placeholders_are_filled_in() {
+        let statement = r#"CREATE TABLE t ("key" {key} PRIMARY KEY, def {text}){strict};"#;
+        assert_eq!(
+            Dialect::Sqlite.ddl(statement),
+            r#"CREATE TABLE t ("key" TEXT PRIMARY KEY, def TEXT) STRICT;"#
+        );
+        assert_eq!(
+            Dialect::Postgres.ddl(statement),
+            r#"CREATE TABLE t ("key" TEXT PRIMARY KEY, def TEXT);"#
+        );
+        assert_eq!(
+            Dialect::MySql.ddl(statement),
+            "CREATE TABLE t (`key` VARCHAR(255) COLLATE utf8mb4_bin PRIMARY KEY, def LONGTEXT);"
+        );
+    }
+}
@@ -0,0 +1,54 @@
+/// Appends `text` to `output`, escaping the characters that would otherwise be read as markup.
+pub fn push_escaped_text(output: &mut String, text: &str) {
+    for c in text.chars() {
+        match c {
+            '&' => output.push_str("&amp;"),
+            '<' => output.push_str("&lt;"),
+            '>' => output.push_str("&gt;"),
+            _ => output.push(c),
+        }
+    }
+}
+
+/// Appends `value` to `output` escaped for use inside a double or single quoted attribute value.
+pub fn push_escaped_attr(output: &mut String, value: &str) {
+    for c in value.chars() {
+        match c {
+            '&' => output.push_str("&amp;"),
+            '<' => output.push_str("&lt;"),
+            '>' => output.push_str("&gt;"),
+            '"' => output.push_str("&quot;"),
+            '\'' => output.push_str("&#39;"),
+            _ => output.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_escapes_markup_only() {
+        let mut output = String::from("<b>");
+        push_escaped_text(&mut output, r#"a < b && c > "d" 'λ'"#);
+        assert_eq!(output, r#"<b>a &lt; b &amp;&amp; c &gt; "d" 'λ'"#);
+        // escaping is not undone or repeated
+        let mut output = String::new();
+        push_escaped_text(&mut output, "&amp;");
+        assert_eq!(output, "&amp;amp;");
+    }
+
+    #[test]
+    fn attributes_escape_quotes_too() {
+        let mut output = String::from("<a title=\"");
+        push_escaped_attr(&mut output, r#"a < b & "c" 'd' λύω"#);
+        assert_eq!(
+            output,
+            "<a title=\"a &lt; b &amp; &quot;c&quot; &#39;d&#39; λύω"
+        );
+        let mut output = String::new();
+        push_escaped_attr(&mut output, "");
+        assert_eq!(output, "");
+    }
+}
@@ -1,14 +1,12 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 
-use tantivy::collector::TopDocs;
-use tantivy::query::QueryParser;
+use tantivy::collector::DocSetCollector;
+use tantivy::query::TermQuery;
 use tantivy::schema::*;
-use tantivy::{Index, IndexWriter, ReloadPolicy};
-use tantivy::tokenizer::*;
+use tantivy::{Index, IndexWriter, ReloadPolicy, Searcher};
 // use tempfile::TempDir;
 
-use quick_xml::events::Event;
-use quick_xml::name::QName;
+use quick_xml::events::{BytesStart, Event};
 use quick_xml::reader::Reader;
 
 use git2::Repository;
@@ -16,7 +14,9 @@ use git2::Repository;
 use std::fs;
 use std::io;
 use std::io::Write;
+use std::ops::Range;
 use std::path::Path;
+use std::time::Instant;
 
 // use quick_xml::events::BytesStart;
 // use std::io::Cursor;
@@ -29,47 +29,321 @@ use sqlx::any::install_default_drivers;
 
 use polytonic_greek::hgk_strip_diacritics;
 
+mod analysis;
+mod authors;
+mod build_record;
+mod citations;
+mod cli;
+mod config;
+mod diagnostics;
+mod dialect;
+mod html;
+mod migrations;
+mod render;
+mod search;
+mod senses;
+mod sources;
+mod staging;
+use analysis::{
+    DEFINITION_TOKENIZER, ENGLISH_TOKENIZER, FieldTexts, GREEK_TOKENIZER, LATIN_TOKENIZER,
+    LEMMA_PREFIX_TOKENIZER, LEMMA_TOKENIZER, TextLang, definition_analyzer, english_analyzer,
+    greek_analyzer, is_greek, latin_analyzer, lemma_analyzer, lemma_prefix_analyzer,
+    normalize_lemma, tokenize_definition,
+};
+use authors::{AuthorList, AuthorRef};
+use build_record::BuildRecord;
+use citations::Citation;
+use cli::{Args, Command};
+use config::Lexicon;
+use diagnostics::Diagnostics;
+use dialect::Dialect;
+use html::{push_escaped_attr, push_escaped_text};
+use render::Renderer;
+use search::{SearchRequest, SearchResults};
+use senses::{Sense, SenseTreeBuilder};
+use sources::LineIndex;
+use staging::StagedPath;
+
 static OUTPUT: &str = "output.txt";
 
-#[derive(Clone)]
-struct Lexicon<'a> {
-    dir_name: &'a str,
-    file_name: &'a str,
-    repo_url: &'a str,
-    start_rng: u32,
-    end_rng: u32,
-    name: &'a str,
-    branch: &'a str,
-    remote: &'a str,
-    pull: bool,
+// grammatical information given in an entry's <gramGrp> and <etym> elements
+#[derive(Default)]
+struct GramInfo {
+    pos: String,
+    gender: String,
+    itype: String,
+    etym: String,
+}
+
+#[derive(Clone, Copy)]
+enum GramField {
+    Pos,
+    Gender,
+    Itype,
+    Etym,
+}
+
+impl GramField {
+    fn from_element(name: &[u8]) -> Option<GramField> {
+        match name {
+            b"pos" => Some(GramField::Pos),
+            b"gen" => Some(GramField::Gender),
+            b"itype" => Some(GramField::Itype),
+            b"etym" => Some(GramField::Etym),
+            _ => None,
+        }
+    }
+}
+
+impl GramInfo {
+    fn field_mut(&mut self, field: GramField) -> &mut String {
+        match field {
+            GramField::Pos => &mut self.pos,
+            GramField::Gender => &mut self.gender,
+            GramField::Itype => &mut self.itype,
+            GramField::Etym => &mut self.etym,
+        }
+    }
+
+    fn trim(&mut self) {
+        for field in [
+            GramField::Pos,
+            GramField::Gender,
+            GramField::Itype,
+            GramField::Etym,
+        ] {
+            let value = self.field_mut(field);
+            *value = value.trim().to_string();
+        }
+    }
 }
 
 struct LexEntryCollector {
+    id: String,
+    source_file: String, // relative to the lexicon's directory
+    source_line: usize,  // line of the entry's opening div
     item_text: String,
     item_text_no_tags: String,
+    latin_spans: Vec<Range<usize>>, // byte ranges of item_text_no_tags in Latin-language elements
+    fields: FieldTexts,             // item_text_no_tags split up by language, for the index
     head: String,
-    orth: String,
+    homograph: u32, // 1 for the first entry with this head in the lexicon, 2 for the next...
+    explicit_homograph: Option<u32>, // from n on <head> or <orth>, if the lexicon uses them
+    orths: Vec<String>, // the text of each <orth>, e.g. variant spellings
     sense_count: u32,
+    gram: GramInfo,
+    gram_field: Option<GramField>, // the GramInfo field text is being collected into
+    senses: SenseTreeBuilder,
+    citations: Vec<Citation>,
+    in_bibl: bool, // text goes into the display text of the last citation
+    authors: Vec<AuthorRef>,
+    author_tag: Option<AuthorTag>, // the <author> whose text is being collected
+}
+
+// where an <author>'s opening tag was written, so it can be rewritten once the abbreviation is known
+struct AuthorTag {
+    start: usize, // byte offsets in item_text
+    end: usize,
+    attrs: Vec<(String, String)>,
 }
 
 impl LexEntryCollector {
     fn new() -> Self {
         Self {
+            id: String::from(""),
+            source_file: String::from(""),
+            source_line: 0,
             item_text: String::from(""),
             item_text_no_tags: String::from(""),
+            latin_spans: Vec::new(),
+            fields: FieldTexts::default(),
             head: String::from(""),
-            orth: String::from(""),
+            homograph: 0,
+            explicit_homograph: None,
+            orths: Vec::new(),
             sense_count: 0,
+            gram: GramInfo::default(),
+            gram_field: None,
+            senses: SenseTreeBuilder::default(),
+            citations: Vec::new(),
+            in_bibl: false,
+            authors: Vec::new(),
+            author_tag: None,
         }
     }
 
     fn clear(&mut self) {
+        self.id.clear();
+        self.source_file.clear();
+        self.source_line = 0;
         self.item_text.clear();
         self.item_text_no_tags.clear();
+        self.latin_spans.clear();
+        self.fields = FieldTexts::default();
         self.head.clear();
-        self.orth.clear();
+        self.homograph = 0;
+        self.explicit_homograph = None;
+        self.orths.clear();
         self.sense_count = 0;
+        self.gram = GramInfo::default();
+        self.gram_field = None;
+        self.senses.clear();
+        self.citations.clear();
+        self.in_bibl = false;
+        self.authors.clear();
+        self.author_tag = None;
+    }
+
+    // records the citation of a <bibl> with an n attribute, adding its parsed reference to attrs,
+    // where render rules can use it as if it were TEI attributes; n values that cannot be parsed
+    // go into unparsed. The text of a non-empty bibl goes into the citation's display text.
+    fn start_bibl(
+        &mut self,
+        attrs: &mut Vec<(String, String)>,
+        empty: bool,
+        unparsed: &mut BTreeSet<String>,
+    ) {
+        let Some(n) = get_attr(attrs, "n").map(str::to_string) else {
+            return;
+        };
+        let citation = Citation::new(&n, self.senses.current_path());
+        self.senses.add_citation(&n);
+        match &citation.cts {
+            Some(cts) => attrs.extend(cts.attributes()),
+            None => {
+                unparsed.insert(n);
+            }
+        }
+        self.citations.push(citation);
+        self.in_bibl = !empty;
+    }
+
+    // each distinct orth, with whitespace normalized
+    fn variants(&self) -> Vec<String> {
+        let mut variants: Vec<String> = Vec::new();
+        for orth in &self.orths {
+            let orth = orth.split_whitespace().collect::<Vec<_>>().join(" ");
+            if !orth.is_empty() && !variants.contains(&orth) {
+                variants.push(orth);
+            }
+        }
+        variants
     }
+
+    // numbers an entry that is about to be inserted and returns its seq: an entry in a file being
+    // reloaded keeps the seq and homograph number it had, others get the next seq. Also returns
+    // the entry's homograph number if another entry already has it, in which case it gets the
+    // next free one instead.
+    fn number(
+        &mut self,
+        reloaded: &mut HashMap<String, (i32, u32)>,
+        homographs: &mut Homographs,
+        item_count: &mut i32,
+    ) -> (i32, Option<u32>) {
+        let (seq, old_homograph) = match reloaded.remove(&self.id) {
+            Some((seq, homograph)) => (seq, Some(homograph)),
+            None => {
+                *item_count += 1;
+                (*item_count, None)
+            }
+        };
+        let wanted = self.explicit_homograph;
+        self.homograph = homographs.number(&self.head, wanted.into_iter().chain(old_homograph));
+        let taken = wanted.filter(|n| *n != self.homograph);
+        (seq, taken)
+    }
+
+    // adds text to the plain text of the entry and the language fields
+    fn push_text(&mut self, text: &str, lang: TextLang, in_quote: bool, in_translation: bool) {
+        let start = self.item_text_no_tags.len();
+        self.item_text_no_tags.push_str(text);
+        if lang == TextLang::Latin {
+            self.latin_spans.push(start..self.item_text_no_tags.len());
+        }
+        self.fields
+            .push(start, text, lang, in_quote, in_translation);
+    }
+
+    fn start_author(&mut self, start: usize, attrs: &[(String, String)]) {
+        self.authors.push(AuthorRef::default());
+        self.author_tag = Some(AuthorTag {
+            start,
+            end: self.item_text.len(),
+            attrs: attrs.to_vec(),
+        });
+    }
+
+    // looks up the abbreviation of the <author> just ended and, if it is in the author list, adds
+    // its full name and id to the opening tag; returns the abbreviation if it is not in the list
+    // or names several authors and the citation does not say which
+    fn end_author(&mut self, author_list: &AuthorList, renderer: &Renderer) -> Option<String> {
+        let tag = self.author_tag.take()?;
+        let author_ref = self.authors.last_mut()?;
+        author_ref.abbrev = author_ref
+            .abbrev
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ");
+        // the author id of the reference the <author> is in, if any
+        let cited_id = self
+            .citations
+            .last()
+            .filter(|_| self.in_bibl)
+            .and_then(|citation| citation.cts.as_ref())
+            .map(|cts| cts.author.as_str());
+        let Some(author) = author_list.get(&author_ref.abbrev, cited_id) else {
+            return Some(author_ref.abbrev.clone());
+        };
+
+        // available to render rules as if they were TEI attributes
+        let mut attrs = tag.attrs;
+        attrs.push(("expan".to_string(), author.name.clone()));
+        if let Some(id) = &author.id {
+            attrs.push(("key".to_string(), id.clone()));
+        }
+        let mut open = String::new();
+        renderer.render("author", &attrs, false, &mut open);
+        self.item_text.replace_range(tag.start..tag.end, &open);
+        author_ref.author = Some(author.clone());
+        None
+    }
+
+    fn start_gram_field(&mut self, field: GramField) {
+        let value = self.gram.field_mut(field);
+        if !value.trim().is_empty() {
+            value.push_str("; "); // e.g. a second <pos> in the same entry
+        }
+        self.gram_field = Some(field);
+    }
+}
+
+// collects an element's attributes as (name, unescaped value) pairs
+fn attributes(e: &BytesStart) -> anyhow::Result<Vec<(String, String)>> {
+    let mut attrs = Vec::new();
+    for a in e.attributes() {
+        let a = a?;
+        attrs.push((
+            std::str::from_utf8(a.key.as_ref())?.to_string(),
+            a.unescape_value()?.to_string(),
+        ));
+    }
+    Ok(attrs)
+}
+
+// the language given by an element's lang or xml:lang attribute, if it has one
+fn element_lang(e: &BytesStart) -> Option<TextLang> {
+    let lang = match e.try_get_attribute("xml:lang") {
+        Ok(Some(lang)) => lang,
+        _ => e.try_get_attribute("lang").ok()??,
+    };
+    Some(TextLang::from_attr(&String::from_utf8_lossy(&lang.value)))
+}
+
+fn get_attr<'a>(attrs: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    attrs
+        .iter()
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value.as_str())
 }
 
 fn sanitize_sort_key(str: &str) -> String {
@@ -89,76 +363,296 @@ fn sanitize_sort_key(str: &str) -> String {
     s
 }
 
-struct Processor<'a> {
-    lexica: Vec<Lexicon<'a>>,
-    index_writer: IndexWriter,
-    db: AnyConnection,
-    unique_hashmap: HashMap<String, u32>, // to add numbers to end of non-unique lemmata
+// the homograph numbers given so far in the lexicon being loaded
+#[derive(Default)]
+struct Homographs {
+    highest: HashMap<String, u32>, // highest number given or reserved for each head
+    used: HashSet<(String, u32)>,
 }
 
-impl Processor<'_> {
+impl Homographs {
+    fn clear(&mut self) {
+        self.highest.clear();
+        self.used.clear();
+    }
+
+    // makes entries without an explicit number be numbered after n
+    fn reserve(&mut self, head: &str, n: u32) {
+        let highest = self.highest.entry(head.to_string()).or_insert(0);
+        *highest = (*highest).max(n);
+    }
+
+    // records a number that an entry kept from an earlier build has
+    fn keep(&mut self, head: &str, n: u32) {
+        self.reserve(head, n);
+        self.used.insert((head.to_string(), n));
+    }
+
+    // the first of the wanted numbers that is free, otherwise the one after the highest
+    fn number(&mut self, head: &str, wanted: impl IntoIterator<Item = u32>) -> u32 {
+        let highest = self.highest.entry(head.to_string()).or_insert(0);
+        let n = wanted
+            .into_iter()
+            .find(|n| !self.used.contains(&(head.to_string(), *n)))
+            .unwrap_or(*highest + 1);
+        *highest = (*highest).max(n);
+        self.used.insert((head.to_string(), n));
+        n
+    }
+}
+
+struct Processor {
+    lexica: Vec<Lexicon>,
+    index_writer: Option<IndexWriter>, // None when only rebuilding the db
+    index_fields: Option<IndexFields>, // the fields of index_writer's schema
+    db: Option<AnyConnection>,         // None when only rebuilding the index
+    pull: bool,
+    incremental: bool, // keep the entries of lexica whose repository commit has not changed
+    diagnostics: Diagnostics,
+    unknown_elements: BTreeMap<String, usize>, // elements in entries with no render rule
+    unparsed_citations: BTreeSet<String>,      // bibl n values that are not CTS references
+    unknown_authors: BTreeMap<String, usize>,  // author abbreviations missing from author lists
+    homographs: Homographs,                    // homograph numbers given in the current lexicon
+    reloaded: HashMap<String, (i32, u32)>, // seq and homograph by entry id of entries in reloaded files
+    registry: BTreeSet<String>,            // enabled lexica, kept when --lexicon leaves them out
+    // for an index-only build, the db whose seqs and homograph numbers the documents take
+    numbering_db: Option<AnyConnection>,
+}
+
+impl Processor {
     async fn db_insert_word<'a, 'b>(
         tx: &'a mut sqlx::Transaction<'b, sqlx::Any>,
         item_count: i32,
         lexicon_name: &str,
-        lemma: &str,
-        def: &str,
+        entry: &LexEntryCollector,
+        senses: &[Sense],
     ) -> Result<(), sqlx::Error> {
+        let dialect = Dialect::of(tx);
+        let lemma = entry.head.as_str();
+        let gram = &entry.gram;
         //println!("{} {}", item_count, lemma);
-        let query =
-            r#"INSERT INTO words (seq, lexicon, word, sortword, def) VALUES ($1, $2, $3, $4, $5);"#;
-        let _ = sqlx::query(query)
+        let query = dialect.sql(r#"INSERT INTO words (seq, lexicon, word, homograph, entry_id, source_file, source_line, sortword, def, pos, gender, itype, etym, senses) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14);"#);
+        let _ = sqlx::query(&query)
             .bind(item_count)
             .bind(lexicon_name)
             .bind(lemma)
+            .bind(entry.homograph as i32)
+            .bind(entry.id.as_str())
+            .bind(entry.source_file.as_str())
+            .bind(entry.source_line as i64)
             .bind(sanitize_sort_key(lemma).as_str())
-            .bind(def)
+            .bind(entry.item_text.as_str())
+            .bind(gram.pos.as_str())
+            .bind(gram.gender.as_str())
+            .bind(gram.itype.as_str())
+            .bind(gram.etym.as_str())
+            .bind(serde_json::to_string(senses).unwrap())
             .execute(&mut **tx)
             .await?;
+
+        let query = dialect.sql(r#"INSERT INTO senses (seq, ord, path, level, n, text) VALUES ($1, $2, $3, $4, $5, $6);"#);
+        for (ord, sense) in senses::flatten(senses).iter().enumerate() {
+            sqlx::query(&query)
+                .bind(item_count)
+                .bind(ord as i32)
+                .bind(sense.path.as_str())
+                .bind(sense.level as i32)
+                .bind(sense.n.as_str())
+                .bind(sense.text.as_str())
+                .execute(&mut **tx)
+                .await?;
+        }
+
+        let query = dialect.sql(r#"INSERT INTO citations (seq, ord, n, text, sense, urn, author, work, passage) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9);"#);
+        for (ord, citation) in entry.citations.iter().enumerate() {
+            let cts = citation.cts.as_ref();
+            sqlx::query(&query)
+                .bind(item_count)
+                .bind(ord as i32)
+                .bind(citation.n.as_str())
+                .bind(citation.text.as_str())
+                .bind(citation.sense.as_str())
+                .bind(cts.map(|c| c.urn()))
+                .bind(cts.map(|c| c.author.clone()))
+                .bind(cts.and_then(|c| c.work.clone()))
+                .bind(cts.and_then(|c| c.passage.clone()))
+                .execute(&mut **tx)
+                .await?;
+        }
+
+        let query = dialect.sql(r#"INSERT INTO variants (seq, ord, orth) VALUES ($1, $2, $3);"#);
+        for (ord, orth) in entry.variants().iter().enumerate() {
+            sqlx::query(&query)
+                .bind(item_count)
+                .bind(ord as i32)
+                .bind(orth.as_str())
+                .execute(&mut **tx)
+                .await?;
+        }
+
+        let query = dialect.sql(r#"INSERT INTO authors (seq, ord, abbrev, name, author_id) VALUES ($1, $2, $3, $4, $5);"#);
+        for (ord, author_ref) in entry.authors.iter().enumerate() {
+            let author = author_ref.author.as_ref();
+            sqlx::query(&query)
+                .bind(item_count)
+                .bind(ord as i32)
+                .bind(author_ref.abbrev.as_str())
+                .bind(author.map(|a| a.name.clone()))
+                .bind(author.and_then(|a| a.id.clone()))
+                .execute(&mut **tx)
+                .await?;
+        }
         Ok(())
     }
 
     fn tantivy_insert_word(
         index_writer: &IndexWriter,
+        fields: &IndexFields,
         item_count: i32,
-        lemma: &str,
         lexicon_name: &str,
-        item_text_no_tags: &str,
+        entry: &LexEntryCollector,
     ) {
-        let word_id_field = index_writer.index().schema().get_field("word_id").unwrap();
-        let lemma_field = index_writer.index().schema().get_field("lemma").unwrap();
-        let lexicon_field = index_writer.index().schema().get_field("lexicon").unwrap();
-        let def_field = index_writer
-            .index()
-            .schema()
-            .get_field("definition")
-            .unwrap();
-
         //println!("{} {}", item_count, lemma);
         let mut doc = TantivyDocument::default();
-        doc.add_u64(word_id_field, item_count.try_into().unwrap());
-        doc.add_text(lemma_field, lemma);
-        doc.add_text(lexicon_field, lexicon_name);
-        doc.add_text(
-            def_field,
-            //hgk_strip_diacritics(item_text_no_tags, 0xFFFFFFFF),
-            item_text_no_tags
+        doc.add_u64(fields.word_id, item_count.try_into().unwrap());
+        doc.add_text(fields.lemma, &entry.head);
+        doc.add_text(fields.lemma_folded, entry.head.trim());
+        doc.add_text(fields.lemma_prefix, entry.head.trim());
+        doc.add_u64(fields.homograph, entry.homograph.into());
+        let mut variants = entry.variants();
+        let folded: Vec<String> = variants
+            .iter()
+            .map(|orth| analysis::fold_latin_letters(orth))
+            .collect();
+        for folded in folded {
+            if !variants.contains(&folded) {
+                variants.push(folded);
+            }
+        }
+        // variants can be looked up like the head, once for each distinct folded form
+        let mut lemmas = vec![normalize_lemma(&entry.head)];
+        for variant in &variants {
+            doc.add_text(fields.variant, variant);
+            let lemma = normalize_lemma(variant);
+            if !lemmas.contains(&lemma) {
+                doc.add_text(fields.lemma_folded, variant);
+                doc.add_text(fields.lemma_prefix, variant);
+                lemmas.push(lemma);
+            }
+        }
+        doc.add_text(fields.lexicon, lexicon_name);
+        doc.add_text(fields.entry_id, &entry.id);
+        doc.add_text(fields.source_file, &entry.source_file);
+        doc.add_u64(fields.source_line, entry.source_line as u64);
+        let definition = entry.item_text_no_tags.trim();
+        let leading = entry.item_text_no_tags.len() - entry.item_text_no_tags.trim_start().len();
+        let latin_spans: Vec<Range<usize>> = entry
+            .latin_spans
+            .iter()
+            .map(|span| span.start.saturating_sub(leading)..span.end.saturating_sub(leading))
+            .collect();
+        doc.add_pre_tokenized_text(
+            fields.definition,
+            tokenize_definition(definition, &latin_spans),
         );
+        let parts = [
+            &entry.fields.gloss_en,
+            &entry.fields.text_grc,
+            &entry.fields.text_la,
+            &entry.fields.quotes,
+            &entry.fields.translations,
+        ];
+        for (field, part) in fields.parts.into_iter().zip(parts) {
+            let text = part.text.trim();
+            if !text.is_empty() {
+                doc.add_text(field, text);
+            }
+        }
+        if !entry.gram.pos.is_empty() {
+            doc.add_text(fields.pos, &entry.gram.pos);
+        }
+        if !entry.gram.gender.is_empty() {
+            doc.add_text(fields.gender, &entry.gram.gender);
+        }
+        for citation in &entry.citations {
+            doc.add_text(fields.citation, &citation.n);
+            if let Some(cts) = &citation.cts {
+                doc.add_text(fields.citation, cts.urn());
+            }
+        }
         index_writer.add_document(doc).unwrap();
     }
 
+    // numbers an entry at its end tag and adds it to the index and the db, unless it is empty;
+    // it takes the parts of the processor it needs separately, since the transaction borrows the
+    // db. Returns the homograph number the entry asked for if another entry already had it.
+    async fn finish_entry(
+        entry: &mut LexEntryCollector,
+        reloaded: &mut HashMap<String, (i32, u32)>,
+        homographs: &mut Homographs,
+        index: Option<(&IndexWriter, &IndexFields)>,
+        tx: Option<&mut sqlx::Transaction<'_, sqlx::Any>>,
+        lexicon_name: &str,
+        item_count: &mut i32,
+    ) -> Result<Option<u32>, sqlx::Error> {
+        entry.item_text.push_str("</div>");
+        if entry.item_text.trim().len() <= 6 {
+            return Ok(None);
+        }
+        entry.gram.trim();
+        let senses = entry.senses.finish();
+        for citation in entry.citations.iter_mut() {
+            citation.text = citation
+                .text
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" ");
+        }
+
+        let (seq, taken) = entry.number(reloaded, homographs, item_count);
+        if let Some((index_writer, fields)) = index {
+            Processor::tantivy_insert_word(index_writer, fields, seq, lexicon_name, entry);
+        }
+        if let Some(tx) = tx {
+            Processor::db_insert_word(tx, seq, lexicon_name, entry, &senses).await?;
+        }
+        Ok(taken)
+    }
+
     async fn read_xml(
         &mut self,
-        file: &str,
-        lexicon_name: &str,
+        file: &Path,
+        lex: &Lexicon,
+        renderer: &Renderer,
+        author_list: &AuthorList,
         item_count: &mut i32,
     ) -> Result<(), sqlx::Error> {
+        let lexicon_name = lex.name.as_str();
         //println!("file: {}", file);
-        let mut reader = Reader::from_file(file).unwrap();
+        let lines = match LineIndex::read(file) {
+            Ok(lines) => lines,
+            Err(e) => {
+                self.diagnostics.error(file, None, 0, "", e);
+                return Ok(());
+            }
+        };
+        let mut reader = match Reader::from_file(file) {
+            Ok(reader) => reader,
+            Err(e) => {
+                self.diagnostics.error(file, Some(&lines), 0, "", e);
+                return Ok(());
+            }
+        };
         reader.config_mut().trim_text(false); //FIX ME: check docs, do we want true here?
         reader.config_mut().enable_all_checks(true);
+        // end tags are matched against open_elements below instead, so that a stray end tag
+        // only costs the entry it is in rather than putting every later end tag out of step
+        reader.config_mut().check_end_names = false;
+        reader.config_mut().allow_unmatched_ends = true;
         //reader.trim_text(false); //false to preserve whitespace
 
+        let source_file = source_file_name(lex, file);
+
         let mut buf = Vec::new();
 
         let mut entry = LexEntryCollector::new();
@@ -167,6 +661,10 @@ impl Processor<'_> {
         let mut in_head_tag = false;
         let mut in_text_tag = false;
         let mut in_entry = false;
+        let mut skip_entry = false; // after an error, skip ahead to the next entry
+        let mut last_error_position = None;
+        // name, the html to close it with, and the language its lang attribute or an ancestor's gives
+        let mut open_elements: Vec<(Vec<u8>, String, Option<TextLang>)> = Vec::new();
 
         // let mut file = OpenOptions::new()
         //     .append(true)
@@ -174,347 +672,752 @@ impl Processor<'_> {
         //     .open(OUTPUT)
         //     .unwrap();
 
-        let mut tx = self.db.begin().await?;
+        let mut tx = match self.db.as_mut() {
+            Some(db) => Some(db.begin().await?),
+            None => None,
+        };
 
         loop {
-            match reader.read_event_into(&mut buf) {
-                Err(e) => panic!(
-                    "XML parsing error at position {}: {:?}",
-                    reader.buffer_position(),
-                    e
-                ),
+            let mut error = None;
+            let mut closing = String::new();
+            let event_start = reader.buffer_position();
+            let event = reader.read_event_into(&mut buf);
+            match &event {
+                Ok(Event::Start(e)) => {
+                    // an element without a language is in the language of its parent
+                    let lang = element_lang(e)
+                        .or_else(|| open_elements.last().and_then(|(_, _, lang)| *lang));
+                    open_elements.push((e.name().as_ref().to_vec(), String::new(), lang))
+                }
+                Ok(Event::End(e)) => {
+                    let name = e.name();
+                    if open_elements.last().map(|(n, _, _)| n.as_slice()) == Some(name.as_ref()) {
+                        closing = open_elements.pop().unwrap().1;
+                    } else if let Some(pos) = open_elements
+                        .iter()
+                        .rposition(|(n, _, _)| n.as_slice() == name.as_ref())
+                    {
+                        let unclosed = open_elements[pos + 1..]
+                            .iter()
+                            .map(|(n, _, _)| format!("<{}>", String::from_utf8_lossy(n)))
+                            .collect::<Vec<_>>()
+                            .join(", ");
+                        error = Some((
+                            reader.buffer_position(),
+                            format!(
+                                "</{}> closes unclosed {}",
+                                String::from_utf8_lossy(name.as_ref()),
+                                unclosed
+                            ),
+                        ));
+                        open_elements.truncate(pos);
+                    } else {
+                        error = Some((
+                            reader.buffer_position(),
+                            format!(
+                                "</{}> does not match any open element",
+                                String::from_utf8_lossy(name.as_ref())
+                            ),
+                        ));
+                    }
+                }
+                _ => (),
+            }
+            match event {
+                // the end tag did not match, so the entry is dropped below
+                Ok(Event::End(_)) if error.is_some() => {}
+                Err(e) => {
+                    if last_error_position == Some(reader.error_position()) {
+                        // the reader cannot get past this error, so give up on the rest of the file
+                        self.diagnostics.error(
+                            file,
+                            Some(&lines),
+                            reader.error_position(),
+                            &entry.id,
+                            "could not recover from previous error, skipping rest of file",
+                        );
+                        break;
+                    }
+                    last_error_position = Some(reader.error_position());
+                    error = Some((reader.error_position(), e.to_string()));
+                }
                 Ok(Event::Eof) => break,
+                // after an error, ignore everything until the next entry starts
+                Ok(Event::Start(e))
+                    if skip_entry && !matches!(e.name().as_ref(), b"text" | b"div1" | b"div2") => {}
+                Ok(Event::End(e)) if skip_entry && e.name().as_ref() != b"text" => {}
+                Ok(Event::Text(_)) | Ok(Event::Empty(_)) if skip_entry => {}
                 Ok(Event::Comment(_e)) => {}
                 Ok(Event::CData(_e)) => {}
                 Ok(Event::Decl(_e)) => {}
                 Ok(Event::PI(_e)) => {}
                 Ok(Event::DocType(_e)) => {}
 
-                Ok(Event::Start(e)) => match e.name().as_ref() {
-                    b"text" => {
-                        in_text_tag = true;
-                    }
-                    b"head" => {
-                        //do not include <head> tags which are not in entries:
-                        // e.g. the letter head tags of Lewis & Short (latindico01.xml)
-                        in_head_tag = true;
-                    }
-                    b"orth" => {
-                        in_orth_tag = true;
-                        entry.item_text.push_str(r#"<span class="orth">"#);
-                    }
-                    b"div1" | b"div2" => {
-                        entry.clear();
+                Ok(Event::Start(e)) => match attributes(&e) {
+                    Err(err) => error = Some((reader.buffer_position(), err.to_string())),
+                    Ok(mut attrs) => match e.name().as_ref() {
+                        b"text" => {
+                            in_text_tag = true;
+                        }
+                        b"head" => {
+                            //do not include <head> tags which are not in entries:
+                            // e.g. the letter head tags of Lewis & Short (latindico01.xml)
+                            in_head_tag = true;
+                            if in_entry
+                                && lex.explicit_homographs
+                                && let Some(n) = get_attr(&attrs, "n").and_then(|n| n.parse().ok())
+                            {
+                                entry.explicit_homograph = Some(n);
+                            }
+                        }
+                        b"div1" | b"div2" => {
+                            entry.clear();
+                            skip_entry = false;
 
-                        entry.item_text.push_str(r#"<div id=""#);
-                        let mut found_id = false;
-                        for a in e.attributes() {
-                            if a.as_ref().unwrap().key == QName(b"id") {
+                            entry.item_text.push_str(r#"<div id=""#);
+                            let mut found_id = false;
+                            if let Some(id) = get_attr(&attrs, "id") {
                                 found_id = true;
                                 in_entry = true;
-                                entry
-                                    .item_text
-                                    .push_str(std::str::from_utf8(&a.unwrap().value).unwrap());
-                                break;
+                                entry.id.push_str(id);
+                                entry.source_file.push_str(&source_file);
+                                entry.source_line = lines.line(event_start);
+                                push_escaped_attr(&mut entry.item_text, id);
+                            }
+                            entry.item_text.push_str(r#"" class="body">"#);
+                            // checking that we found an id prevents treating container <div1> as a word div in lsj
+                            if !found_id {
+                                entry.clear();
                             }
                         }
-                        entry.item_text.push_str(r#"" class="body">"#);
-                        // checking that we found an id prevents treating container <div1> as a word div in lsj
-                        if !found_id {
-                            entry.clear();
-                        }
-                    }
-                    b"sense" => {
-                        if entry.sense_count == 0 {
-                            entry.item_text.push_str(r#"<br/><br/><div class="l"#);
-                        } else {
-                            entry.item_text.push_str(r#"<br/><div class="l"#);
-                        }
-                        let mut label = String::from("");
-                        for a in e.attributes() {
-                            if a.as_ref().unwrap().key == QName(b"level") {
-                                entry
-                                    .item_text
-                                    .push_str(std::str::from_utf8(&a.unwrap().value).unwrap());
-                            } else if a.as_ref().unwrap().key == QName(b"n") {
-                                label.push_str(std::str::from_utf8(&a.unwrap().value).unwrap());
+                        b"sense" => {
+                            if entry.sense_count == 0 {
+                                entry.item_text.push_str(r#"<br/><br/><div class="l"#);
+                            } else {
+                                entry.item_text.push_str(r#"<br/><div class="l"#);
                             }
+                            let mut label = String::from("");
+                            for (key, value) in &attrs {
+                                if key == "level" {
+                                    push_escaped_attr(&mut entry.item_text, value);
+                                } else if key == "n" {
+                                    label.push_str(value);
+                                }
+                            }
+                            entry.item_text.push_str(r#"">"#);
+                            if !label.is_empty() {
+                                entry.item_text.push_str(r#"<span class="label">"#);
+                                push_escaped_text(&mut entry.item_text, &label);
+                                entry.item_text.push_str(".</span>");
+                            }
+                            entry.sense_count += 1;
+                            let level = get_attr(&attrs, "level").and_then(|l| l.parse().ok());
+                            entry.senses.start(level, &label);
                         }
-                        entry.item_text.push_str(r#"">"#);
-                        if !label.is_empty() {
-                            entry.item_text.push_str(
-                                format!(r#"<span class="label">{}.</span>"#, label).as_str(),
-                            );
+                        name => {
+                            // orth and grammatical information are collected as well as rendered
+                            if name == b"orth" {
+                                in_orth_tag = true;
+                                if in_entry {
+                                    entry.orths.push(String::new());
+                                }
+                                if in_entry
+                                    && lex.explicit_homographs
+                                    && entry.explicit_homograph.is_none()
+                                {
+                                    entry.explicit_homograph =
+                                        get_attr(&attrs, "n").and_then(|n| n.parse().ok());
+                                }
+                            }
+                            if in_entry && let Some(field) = GramField::from_element(name) {
+                                entry.start_gram_field(field);
+                            }
+                            if in_entry && name == b"bibl" {
+                                entry.start_bibl(&mut attrs, false, &mut self.unparsed_citations);
+                            }
+                            let tag_start = entry.item_text.len();
+                            let name = String::from_utf8_lossy(name);
+                            match renderer.render(&name, &attrs, false, &mut entry.item_text) {
+                                Some(close) => {
+                                    if in_entry && name == "author" {
+                                        entry.start_author(tag_start, &attrs);
+                                    }
+                                    if let Some((_, closing, _)) = open_elements.last_mut() {
+                                        *closing = close;
+                                    }
+                                }
+                                None => {
+                                    if in_entry {
+                                        *self
+                                            .unknown_elements
+                                            .entry(name.to_string())
+                                            .or_insert(0) += 1;
+                                    }
+                                }
+                            }
                         }
-                        entry.sense_count += 1;
-                    }
-                    b"author" => {
-                        entry.item_text.push_str(r#"<span class="au">"#);
-                    }
-                    b"quote" => {
-                        entry.item_text.push_str(r#"<span class="qu">"#);
+                    },
+                },
+                Ok(Event::End(e)) => match e.name().as_ref() {
+                    b"text" => {
+                        in_text_tag = false;
                     }
-                    b"foreign" => {
-                        entry.item_text.push_str(r#"<span class="fo">"#);
+                    b"head" => {
+                        in_head_tag = false;
                     }
-                    b"i" => {
-                        entry.item_text.push_str(r#"<span class="tr">"#);
+                    b"div1" | b"div2" => {
+                        if in_text_tag
+                            && let Some(n) = Processor::finish_entry(
+                                &mut entry,
+                                &mut self.reloaded,
+                                &mut self.homographs,
+                                self.index_writer.as_ref().zip(self.index_fields.as_ref()),
+                                tx.as_mut(),
+                                lexicon_name,
+                                item_count,
+                            )
+                            .await?
+                        {
+                            let message = format!(
+                                "homograph {} of {} is already taken; numbered {} instead",
+                                n,
+                                entry.head.trim(),
+                                entry.homograph
+                            );
+                            self.diagnostics.error(
+                                file,
+                                Some(&lines),
+                                event_start,
+                                &entry.id,
+                                message,
+                            );
+                        }
+                        in_entry = false;
+                        entry.clear();
                     }
-                    b"title" => {
-                        entry.item_text.push_str(r#"<span class="ti">"#);
+                    b"sense" => {
+                        entry.item_text.push_str("</div>");
+                        entry.senses.end();
                     }
-                    b"bibl" => {
-                        entry.item_text.push_str(r#"<a class="bi" biblink=""#);
-                        for a in e.attributes() {
-                            if a.as_ref().unwrap().key == QName(b"n") {
-                                entry
-                                    .item_text
-                                    .push_str(std::str::from_utf8(&a.unwrap().value).unwrap());
-                                break;
-                            }
+                    name => {
+                        if name == b"orth" {
+                            in_orth_tag = false;
                         }
-                        entry.item_text.push_str(r#"">"#);
-                    }
-                    _ => (),
-                },
-                Ok(Event::End(e)) => {
-                    match e.name().as_ref() {
-                        b"text" => {
-                            in_text_tag = false;
+                        if GramField::from_element(name).is_some() {
+                            entry.gram_field = None;
                         }
-                        b"head" => {
-                            in_head_tag = false;
+                        if name == b"bibl" {
+                            entry.in_bibl = false;
                         }
-                        b"orth" => {
-                            in_orth_tag = false;
-                            entry.item_text.push_str("</span>");
+                        if name == b"author"
+                            && let Some(abbrev) = entry.end_author(author_list, renderer)
+                            && !author_list.is_empty()
+                        {
+                            *self.unknown_authors.entry(abbrev).or_insert(0) += 1;
                         }
-                        b"div1" => {
-                            entry.item_text.push_str("</div>");
-
-                            if in_text_tag && entry.item_text.trim().len() > 6 {
-                                *item_count += 1;
-                                //writeln!(file, "{} {}", item_count, item_text).unwrap();
-
-                                //this fixes issue in borrow checker with multiple mutable refs to self
-                                let index_writer = &self.index_writer;
-                                Processor::tantivy_insert_word(
-                                    index_writer,
-                                    *item_count,
-                                    &entry.head,
-                                    lexicon_name,
-                                    entry.item_text_no_tags.trim(),
-                                );
-
-                                let _ = Processor::db_insert_word(
-                                    &mut tx,
-                                    *item_count,
-                                    lexicon_name,
-                                    &entry.head,
-                                    &entry.item_text,
-                                )
-                                .await;
-
-                                //println!("item: {}", item_text);
-                            }
-                            in_entry = false;
-                            entry.clear();
+                        entry.item_text.push_str(&closing);
+                    }
+                },
+                Ok(Event::Empty(e)) => match attributes(&e) {
+                    Err(err) => error = Some((reader.buffer_position(), err.to_string())),
+                    Ok(mut attrs) => {
+                        // e.g. <lb/>, <pb n="12"/> or a <bibl n="..."/> with no text
+                        let name = String::from_utf8_lossy(e.name().as_ref()).to_string();
+                        if in_entry && name == "bibl" {
+                            entry.start_bibl(&mut attrs, true, &mut self.unparsed_citations);
                         }
-                        b"div2" => {
-                            entry.item_text.push_str("</div>");
-                            //println!("item: {}", item_text);
-                            if in_text_tag && entry.item_text.trim().len() > 6 {
-                                *item_count += 1;
-                                //writeln!(file, "{} {}", item_count, item_text).unwrap();
-
-                                //this fixes issue in borrow checker with multiple mutable refs to self
-                                let index_writer = &self.index_writer;
-                                Processor::tantivy_insert_word(
-                                    index_writer,
-                                    *item_count,
-                                    &entry.head,
-                                    lexicon_name,
-                                    entry.item_text_no_tags.trim(),
-                                );
-
-                                Processor::db_insert_word(
-                                    &mut tx,
-                                    *item_count,
-                                    lexicon_name,
-                                    &entry.head,
-                                    &entry.item_text,
-                                )
-                                .await
-                                .unwrap();
+                        match renderer.render(&name, &attrs, true, &mut entry.item_text) {
+                            Some(close) => entry.item_text.push_str(&close),
+                            None => {
+                                if in_entry {
+                                    *self.unknown_elements.entry(name).or_insert(0) += 1;
+                                }
                             }
-                            in_entry = false;
-                            entry.clear();
                         }
-                        b"sense" => {
-                            entry.item_text.push_str("</div>");
-                        }
-                        b"author" => {
-                            entry.item_text.push_str("</span>");
+                    }
+                },
+                Ok(Event::Text(e)) => match e.unescape() {
+                    Err(err) => error = Some((reader.buffer_position(), err.to_string())),
+                    Ok(text) => {
+                        //txt.push(e.unescape().unwrap().into_owned())
+                        // if in_head_tag {
+
+                        // }
+                        if in_head_tag && in_entry {
+                            entry.head.push_str(&text);
                         }
-                        b"quote" => {
-                            entry.item_text.push_str("</span>");
+                        if in_orth_tag
+                            && in_entry
+                            && let Some(orth) = entry.orths.last_mut()
+                        {
+                            orth.push_str(&text);
                         }
-                        b"foreign" => {
-                            entry.item_text.push_str("</span>");
+                        if let Some(field) = entry.gram_field {
+                            entry.gram.field_mut(field).push_str(&text);
                         }
-                        b"i" => {
-                            entry.item_text.push_str("</span>");
+                        if in_entry {
+                            entry.senses.push_text(&text);
                         }
-                        b"title" => {
-                            entry.item_text.push_str("</span>");
+                        if entry.in_bibl
+                            && let Some(citation) = entry.citations.last_mut()
+                        {
+                            citation.text.push_str(&text);
                         }
-                        b"bibl" => {
-                            entry.item_text.push_str("</a>");
+                        if entry.author_tag.is_some()
+                            && let Some(author_ref) = entry.authors.last_mut()
+                        {
+                            author_ref.abbrev.push_str(&text);
                         }
-                        _ => (),
-                    }
-                }
-                Ok(Event::Empty(_e)) => {}
-                Ok(Event::Text(e)) => {
-                    //txt.push(e.unescape().unwrap().into_owned())
-                    // if in_head_tag {
-
-                    // }
-                    if in_head_tag && in_entry {
-                        let lemma = e.unescape().unwrap().to_string();
-                        // add numbers to end of non-unique lemmata
-                        let count = match self.unique_hashmap.get(&lemma) {
-                            Some(count) => count + 1,
-                            None => 1,
+                        // item_text is html, item_text_no_tags is plain text for the index
+                        push_escaped_text(&mut entry.item_text, &text);
+                        // text not marked with a language is Greek if written in Greek, and
+                        // otherwise English, except in headwords, which are Latin
+                        let lang = open_elements.last().and_then(|(_, _, lang)| *lang);
+                        let lang = match lang {
+                            Some(lang) => lang,
+                            None if is_greek(&text) => TextLang::Greek,
+                            None if in_orth_tag || in_head_tag => TextLang::Latin,
+                            None => TextLang::English,
                         };
-                        entry.head.push_str(
-                            format!(
-                                "{}{}",
-                                lemma,
-                                if count > 1 {
-                                    count.to_string()
-                                } else {
-                                    "".to_string()
-                                }
-                            )
-                            .as_str(),
-                        );
-                        self.unique_hashmap.insert(lemma, count);
-                    }
-                    if in_orth_tag && in_entry {
-                        entry.orth.push_str(&e.unescape().unwrap());
+                        let within = |element: &[u8]| {
+                            open_elements
+                                .iter()
+                                .any(|(n, _, _)| n.as_slice() == element)
+                        };
+                        entry.push_text(&text, lang, within(b"quote"), within(b"i"));
                     }
-                    entry.item_text.push_str(&e.unescape().unwrap());
-                    entry.item_text_no_tags.push_str(&e.unescape().unwrap());
-                }
+                },
+            }
+            if let Some((position, message)) = error {
+                // drop the entry the error occurred in and carry on from the next one
+                self.diagnostics
+                    .error(file, Some(&lines), position, &entry.id, message);
+                entry.clear();
+                in_entry = false;
+                in_head_tag = false;
+                in_orth_tag = false;
+                skip_entry = true;
             }
             buf.clear();
         }
-        tx.commit().await?;
+        if let Some(tx) = tx {
+            tx.commit().await?;
+        }
         Ok(())
     }
 
-    async fn start(&mut self) -> Result<(), sqlx::Error> {
-        let query = "CREATE TABLE IF NOT EXISTS words (seq INTEGER PRIMARY KEY, lexicon TEXT, word TEXT, sortword TEXT, def TEXT) STRICT; \
-        CREATE INDEX IF NOT EXISTS lexicon_idx ON words (lexicon); \
-        CREATE INDEX IF NOT EXISTS sortword_idx ON words (sortword); \
-        CREATE INDEX IF NOT EXISTS word_idx ON words (word);";
+    // removes the rows and documents of the entries from some of a lexicon's files, remembering
+    // their seqs and homograph numbers for when the files are loaded again
+    async fn delete_files(
+        &mut self,
+        lexicon_name: &str,
+        files: &BTreeSet<String>,
+    ) -> anyhow::Result<()> {
+        let Some(db) = self.db.as_mut() else {
+            return Ok(());
+        };
+        let dialect = Dialect::of(db);
+
+        // new homographs are numbered after all the old ones, including those being reloaded
+        let query =
+            dialect.sql("SELECT word, MAX(homograph) FROM words WHERE lexicon = $1 GROUP BY word;");
+        let highest: Vec<(String, i32)> = sqlx::query_as(&query)
+            .bind(lexicon_name)
+            .fetch_all(&mut *db)
+            .await?;
+        for (word, homograph) in highest {
+            self.homographs.reserve(&word, homograph as u32);
+        }
 
-        let _res = sqlx::query(query).execute(&mut self.db).await;
+        for file in files {
+            let query = dialect.sql(
+                "SELECT seq, entry_id, homograph FROM words WHERE lexicon = $1 AND source_file = $2;",
+            );
+            let entries: Vec<(i32, String, i32)> = sqlx::query_as(&query)
+                .bind(lexicon_name)
+                .bind(file.as_str())
+                .fetch_all(&mut *db)
+                .await?;
+            for (seq, entry_id, homograph) in entries {
+                if let (Some(index_writer), Some(fields)) = (&self.index_writer, &self.index_fields)
+                {
+                    index_writer.delete_term(Term::from_field_u64(fields.word_id, seq as u64));
+                }
+                self.reloaded.insert(entry_id, (seq, homograph as u32));
+            }
+
+            for table in migrations::DATA_TABLES.iter().filter(|t| **t != "words") {
+                let query = format!(
+                    "DELETE FROM {} WHERE seq IN (SELECT seq FROM words WHERE lexicon = $1 AND source_file = $2);",
+                    table
+                );
+                sqlx::query(&dialect.sql(&query))
+                    .bind(lexicon_name)
+                    .bind(file.as_str())
+                    .execute(&mut *db)
+                    .await?;
+            }
+            sqlx::query(&dialect.sql("DELETE FROM words WHERE lexicon = $1 AND source_file = $2;"))
+                .bind(lexicon_name)
+                .bind(file.as_str())
+                .execute(&mut *db)
+                .await?;
+        }
 
-        let query = "DELETE FROM words;";
-        let _res = sqlx::query(query).execute(&mut self.db).await;
+        // the entries that are kept hold on to their numbers
+        let query = dialect.sql("SELECT word, homograph FROM words WHERE lexicon = $1;");
+        let kept: Vec<(String, i32)> = sqlx::query_as(&query)
+            .bind(lexicon_name)
+            .fetch_all(&mut *db)
+            .await?;
+        for (word, homograph) in kept {
+            self.homographs.keep(&word, homograph as u32);
+        }
+        Ok(())
+    }
+
+    // removes a lexicon's rows from the db and its documents from the index
+    async fn delete_lexicon(&mut self, lexicon_name: &str) -> anyhow::Result<()> {
+        if let Some(db) = self.db.as_mut() {
+            let dialect = Dialect::of(db);
+            for table in migrations::DATA_TABLES.iter().filter(|t| **t != "words") {
+                let query = format!(
+                    "DELETE FROM {} WHERE seq IN (SELECT seq FROM words WHERE lexicon = $1);",
+                    table
+                );
+                sqlx::query(&dialect.sql(&query))
+                    .bind(lexicon_name)
+                    .execute(&mut *db)
+                    .await?;
+            }
+            sqlx::query(&dialect.sql("DELETE FROM words WHERE lexicon = $1;"))
+                .bind(lexicon_name)
+                .execute(&mut *db)
+                .await?;
+        }
+        if let (Some(index_writer), Some(fields)) = (&self.index_writer, &self.index_fields) {
+            index_writer.delete_term(Term::from_field_text(fields.lexicon, lexicon_name));
+        }
+        Ok(())
+    }
+
+    async fn start(&mut self) -> anyhow::Result<()> {
+        // what the db and index being updated were built from
+        let mut previous = Vec::new();
+        if let Some(db) = self.db.as_mut() {
+            if migrations::migrate(db).await? {
+                self.incremental = false;
+            }
+            previous.push(BuildRecord::read_db(db).await?);
+        }
+        if let Some(index_writer) = &self.index_writer {
+            previous.push(BuildRecord::read_index(index_writer.index())?);
+        }
+        if !previous.iter().all(BuildRecord::is_current) {
+            self.incremental = false;
+        }
 
         let mut item_count: i32 = 0;
+        let mut record = BuildRecord::new();
+        // lexica that were loaded before but are not being loaded now
+        let unselected: BTreeSet<String> = previous
+            .iter()
+            .flat_map(|record| record.lexica.iter())
+            .filter(|name| !self.lexica.iter().any(|lex| &lex.name == *name))
+            .cloned()
+            .collect();
+        if self.incremental {
+            for name in unselected {
+                if self.registry.contains(&name) {
+                    // left out by --lexicon, so its entries are kept as they are
+                    let commit = previous
+                        .first()
+                        .and_then(|p| p.commits.get(&name))
+                        .filter(|c| previous.iter().all(|p| p.commits.get(&name) == Some(*c)));
+                    record.add(&name, commit.cloned());
+                } else {
+                    println!("Removing {}...", name);
+                    self.delete_lexicon(&name).await?;
+                }
+            }
+        } else {
+            // a build that has to start again, e.g. after a migration, would lose the lexica left out
+            let left_out: Vec<&str> = unselected
+                .iter()
+                .filter(|name| self.registry.contains(*name))
+                .map(String::as_str)
+                .collect();
+            ensure_none_left_out(&left_out)?;
+            if let Some(db) = self.db.as_mut() {
+                // every load replaces all the rows
+                for table in migrations::DATA_TABLES {
+                    sqlx::query(&format!("DELETE FROM {};", table))
+                        .execute(&mut *db)
+                        .await?;
+                }
+            }
+            if let Some(index_writer) = &self.index_writer {
+                index_writer.delete_all_documents()?;
+            }
+        }
+        if let Some(db) = self.numbering_db.as_mut() {
+            // entries missing from the db are numbered after it, so they cannot take a seq in it
+            item_count = max_seq(db).await?;
+        }
 
         for lex in self.lexica.clone() {
-            if lex.pull {
-                if !Path::new(&lex.dir_name).exists() {
-                    println!("Cloning {}...", &lex.repo_url);
-
-                    let _repo = match git2::Repository::clone(lex.repo_url, lex.dir_name) {
-                        Ok(repo) => repo,
-                        Err(e) => panic!("failed to clone: {}", e),
-                    };
-                } else if let Ok(repo) = git2::Repository::discover(lex.dir_name) {
-                    //else pull: i.e. fetch and merge
-                    let mut remote = repo.find_remote(lex.remote).unwrap();
-                    let fetch_commit = do_fetch(&repo, &[lex.branch], &mut remote).unwrap();
-                    let _ = do_merge(&repo, lex.branch, fetch_commit);
+            if self.pull && lex.pull {
+                fetch_lexicon(&lex);
+            }
+            let commit = head_commit(&lex);
+            record.add(&lex.name, commit.clone());
+            let mut reload_files = None;
+
+            if self.incremental {
+                if let Some(commit) = &commit
+                    && previous
+                        .iter()
+                        .all(|p| p.commits.get(&lex.name) == Some(commit))
+                {
+                    println!(
+                        "{} is unchanged at {}, keeping its entries",
+                        &lex.display_name,
+                        &commit[..commit.len().min(10)]
+                    );
+                    continue;
+                }
+                // carry on numbering after the entries already loaded
+                if let Some(db) = self.db.as_mut() {
+                    item_count = item_count.max(max_seq(db).await?);
+                }
+
+                // if the lexicon was loaded from an earlier commit, only the files changed since
+                // then need loading again
+                let previous_commit = previous
+                    .first()
+                    .and_then(|p| p.commits.get(&lex.name))
+                    .filter(|c| {
+                        previous
+                            .iter()
+                            .all(|p| p.commits.get(&lex.name) == Some(*c))
+                    });
+                let changed = match (previous_commit, &commit) {
+                    (Some(from), Some(to)) => sources::changed_files(&lex, from, to),
+                    _ => None,
+                };
+                match changed {
+                    Some(files) => {
+                        println!(
+                            "Reloading {} changed file(s) of {}...",
+                            files.len(),
+                            &lex.display_name
+                        );
+                        self.delete_files(&lex.name, &files).await?;
+                        reload_files = Some(files);
+                    }
+                    None => self.delete_lexicon(&lex.name).await?,
                 }
             }
 
-            for i in lex.start_rng..=lex.end_rng {
-                let path = format!("{}{}{:02}.xml", &lex.dir_name, &lex.file_name, i);
-                //println!("path: {}", path);
-                if lex.file_name == "latindico" && i == 10 {
-                    //there is no file for words starting with "j"
-                    continue;
+            if let Some(db) = self.numbering_db.as_mut() {
+                // each document takes the seq and homograph number of its entry in the db
+                let query = Dialect::of(db)
+                    .sql("SELECT seq, entry_id, homograph FROM words WHERE lexicon = $1;");
+                let entries: Vec<(i32, String, i32)> = sqlx::query_as(&query)
+                    .bind(&lex.name)
+                    .fetch_all(&mut *db)
+                    .await?;
+                for (seq, entry_id, homograph) in entries {
+                    self.reloaded.insert(entry_id, (seq, homograph as u32));
+                }
+            }
+
+            let renderer = Renderer::load(lex.render_rules.as_deref())?;
+            let author_list = AuthorList::load(lex.authors.as_deref())?;
+            let mut files = sources::source_files(&lex)?;
+            if let Some(reload) = &reload_files {
+                files.retain(|path| reload.contains(&source_file_name(&lex, path)));
+            } else {
+                println!("Loading {}...", &lex.display_name);
+            }
+            if lex.explicit_homographs {
+                for path in &files {
+                    reserve_explicit_homographs(path, &mut self.homographs);
                 }
-                let _ = self.read_xml(&path, lex.name, &mut item_count).await;
             }
-            self.unique_hashmap.clear(); // clear for next lexicon
+            for path in files {
+                //println!("path: {}", path.display());
+                self.read_xml(&path, &lex, &renderer, &author_list, &mut item_count)
+                    .await?;
+            }
+            self.homographs.clear(); // numbered per lexicon
+            self.reloaded.clear();
             println!("items: {}", item_count);
         }
 
-        self.index_writer.commit().unwrap();
+        if let Some(index_writer) = self.index_writer.as_mut() {
+            let mut commit = index_writer.prepare_commit()?;
+            commit.set_payload(&record.to_payload());
+            commit.commit()?;
+        }
 
-        let query = "VACUUM;";
-        let _res = sqlx::query(query).execute(&mut self.db).await;
+        if let Some(db) = self.db.as_mut() {
+            record.write_db(db).await?;
+            if let Some(query) = Dialect::of(db).vacuum() {
+                let _res = sqlx::query(query).execute(db).await;
+            }
+        }
         Ok(())
     }
 }
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    if Path::new(OUTPUT).is_file() {
-        fs::remove_file(OUTPUT).expect("File delete failed");
+// reserves the homograph numbers given by n on <head> or <orth> in a source file, so that entries
+// without one are numbered after them; errors are left for read_xml to report
+fn reserve_explicit_homographs(file: &Path, homographs: &mut Homographs) {
+    let Ok(mut reader) = Reader::from_file(file) else {
+        return;
+    };
+    reader.config_mut().check_end_names = false;
+    reader.config_mut().allow_unmatched_ends = true;
+    let mut buf = Vec::new();
+    let mut head = String::new();
+    let mut explicit = None;
+    let mut in_entry = false;
+    let mut in_head_tag = false;
+    loop {
+        match reader.read_event_into(&mut buf) {
+            Ok(Event::Start(e)) => {
+                let Ok(attrs) = attributes(&e) else {
+                    continue;
+                };
+                let n = get_attr(&attrs, "n").and_then(|n| n.parse().ok());
+                match e.name().as_ref() {
+                    b"div1" | b"div2" => {
+                        in_entry = get_attr(&attrs, "id").is_some();
+                        head.clear();
+                        explicit = None;
+                    }
+                    b"head" => {
+                        in_head_tag = true;
+                        if in_entry && n.is_some() {
+                            explicit = n;
+                        }
+                    }
+                    b"orth" if in_entry && explicit.is_none() => explicit = n,
+                    _ => {}
+                }
+            }
+            Ok(Event::End(e)) => match e.name().as_ref() {
+                b"head" => in_head_tag = false,
+                b"div1" | b"div2" => {
+                    if in_entry && let Some(n) = explicit {
+                        homographs.reserve(&head, n);
+                    }
+                    in_entry = false;
+                }
+                _ => {}
+            },
+            Ok(Event::Text(e)) if in_entry && in_head_tag => {
+                if let Ok(text) = e.unescape() {
+                    head.push_str(&text);
+                }
+            }
+            Ok(Event::Eof) | Err(_) => break,
+            _ => {}
+        }
+        buf.clear();
     }
+}
 
-    let lsj = Lexicon {
-        dir_name: "LSJLogeion/",
-        file_name: "greatscott",
-        repo_url: "https://github.com/helmadik/LSJLogeion.git",
-        start_rng: 2,
-        end_rng: 86,
-        name: "lsj",
-        branch: "master",
-        remote: "origin",
-        pull: true,
-    };
-    let ls = Lexicon {
-        dir_name: "LewisShortLogeion/",
-        file_name: "latindico",
-        repo_url: "https://github.com/helmadik/LewisShortLogeion.git",
-        start_rng: 1,
-        end_rng: 25,
-        name: "lewisshort",
-        branch: "master",
-        remote: "origin",
-        pull: true,
-    };
-    let slater = Lexicon {
-        dir_name: "SlaterPindar/",
-        file_name: "pindar_dico",
-        repo_url: "https://github.com/jeremymarch/SlaterPindar.git",
-        //repo_url: "https://github.com/helmadik/SlaterPindar.git",
-        start_rng: 1,
-        end_rng: 24,
-        name: "slater",
-        branch: "main",
-        remote: "origin",
-        pull: false,
-    };
+/// Clones the lexicon's repository, or fetches and merges if it is already present.
+fn fetch_lexicon(lex: &Lexicon) {
+    if !Path::new(&lex.dir_name).exists() {
+        println!("Cloning {}...", &lex.repo_url);
 
-    let index_path = "tantivy-datav4"; // TempDir::new()?;
-    if Path::new(index_path).is_dir() {
-        fs::remove_dir_all(index_path).unwrap();
+        let _repo = match git2::Repository::clone(&lex.repo_url, &lex.dir_name) {
+            Ok(repo) => repo,
+            Err(e) => panic!("failed to clone: {}", e),
+        };
+    } else if let Ok(repo) = git2::Repository::discover(&lex.dir_name) {
+        //else pull: i.e. fetch and merge
+        let mut remote = repo.find_remote(&lex.remote).unwrap();
+        let fetch_commit = do_fetch(&repo, &[&lex.branch], &mut remote).unwrap();
+        let _ = do_merge(&repo, &lex.branch, fetch_commit);
     }
-    fs::create_dir(index_path).unwrap();
+}
+
+/// The path of a source file shown to editors and stored in source_file, relative to the lexicon's
+/// directory, e.g. greatscott02.xml rather than LSJLogeion/greatscott02.xml.
+fn source_file_name(lex: &Lexicon, file: &Path) -> String {
+    file.strip_prefix(&lex.dir_name)
+        .unwrap_or(file)
+        .to_string_lossy()
+        .to_string()
+}
+
+/// The commit checked out in the lexicon's repository, if it is a git repository.
+fn head_commit(lex: &Lexicon) -> Option<String> {
+    let repo = Repository::discover(&lex.dir_name).ok()?;
+    let commit = repo.head().ok()?.peel_to_commit().ok()?;
+    Some(commit.id().to_string())
+}
 
+// the fields of the index schema, looked up once for all the documents added
+struct IndexFields {
+    word_id: Field,
+    lemma: Field,
+    lemma_folded: Field,
+    lemma_prefix: Field,
+    homograph: Field,
+    variant: Field,
+    entry_id: Field,
+    source_file: Field,
+    source_line: Field,
+    lexicon: Field,
+    definition: Field,
+    parts: [Field; 5], // gloss_en, text_grc, text_la, quotes and translations
+    pos: Field,
+    gender: Field,
+    citation: Field,
+}
+
+impl IndexFields {
+    fn new(schema: &Schema) -> tantivy::Result<Self> {
+        Ok(Self {
+            word_id: schema.get_field("word_id")?,
+            lemma: schema.get_field("lemma")?,
+            lemma_folded: schema.get_field("lemma_folded")?,
+            lemma_prefix: schema.get_field("lemma_prefix")?,
+            homograph: schema.get_field("homograph")?,
+            variant: schema.get_field("variant")?,
+            entry_id: schema.get_field("entry_id")?,
+            source_file: schema.get_field("source_file")?,
+            source_line: schema.get_field("source_line")?,
+            lexicon: schema.get_field("lexicon")?,
+            definition: schema.get_field("definition")?,
+            parts: [
+                schema.get_field("gloss_en")?,
+                schema.get_field("text_grc")?,
+                schema.get_field("text_la")?,
+                schema.get_field("quotes")?,
+                schema.get_field("translations")?,
+            ],
+            pos: schema.get_field("pos")?,
+            gender: schema.get_field("gender")?,
+            citation: schema.get_field("citation")?,
+        })
+    }
+}
+
+// positions make the index larger, but are needed for phrase and proximity queries on definitions
+fn build_schema(positions: bool) -> Schema {
     let text_field_indexing = TextFieldIndexing::default()
-        .set_tokenizer("el_stem"); // Use the registered name
-    //.set_index_option(IndexRecordOption::WithFreqsAndPositions);
+        .set_tokenizer(DEFINITION_TOKENIZER) // Use the registered name
+        .set_index_option(if positions {
+            IndexRecordOption::WithFreqsAndPositions
+        } else {
+            IndexRecordOption::Basic
+        });
     // let lemma_text_options = TextOptions::default()
     //     //.set_indexing_options(text_field_indexing)
     //     .set_stored();
@@ -524,75 +1427,532 @@ async fn main() -> anyhow::Result<()> {
     let def_text_options = TextOptions::default()
         .set_indexing_options(text_field_indexing)
         .set_stored();
+    // fields indexed with one of the analyzers in register_tokenizers, but not stored
+    let indexed_options = |tokenizer| {
+        TextOptions::default()
+            .set_indexing_options(TextFieldIndexing::default().set_tokenizer(tokenizer))
+    };
 
     let mut schema_builder = Schema::builder();
     let num_options = NumericOptions::default().set_stored().set_indexed();
     schema_builder.add_u64_field("word_id", num_options);
     schema_builder.add_text_field("lemma", STRING | FAST | STORED); //STORED // lemma is also in definition, so no need to index it separately
+    // the lemma without diacritics, whole and as prefixes, for fuzzy and type-ahead lookups
+    schema_builder.add_text_field("lemma_folded", indexed_options(LEMMA_TOKENIZER));
+    schema_builder.add_text_field("lemma_prefix", indexed_options(LEMMA_PREFIX_TOKENIZER));
+    schema_builder.add_u64_field("homograph", STORED);
+    schema_builder.add_text_field("variant", STRING | STORED); // every <orth> of the entry, for lookups on other spellings
+    schema_builder.add_text_field("entry_id", STRING | STORED); // TEI id of the entry's div
+    schema_builder.add_text_field("source_file", STORED);
+    schema_builder.add_u64_field("source_line", STORED);
     //schema_builder.add_text_field("lexicon", lex_text_options); //TEXT | STORED
     schema_builder.add_text_field("lexicon", STRING | FAST | STORED); //doc.add_text(status, "active");
     schema_builder.add_text_field("definition", def_text_options); // TEXT | STORED
-    let schema = schema_builder.build();
+    // parts of the definition by language or role
+    schema_builder.add_text_field("gloss_en", indexed_options(ENGLISH_TOKENIZER));
+    schema_builder.add_text_field("text_grc", indexed_options(GREEK_TOKENIZER));
+    schema_builder.add_text_field("text_la", indexed_options(LATIN_TOKENIZER));
+    schema_builder.add_text_field("quotes", indexed_options(DEFINITION_TOKENIZER));
+    schema_builder.add_text_field("translations", indexed_options(ENGLISH_TOKENIZER));
+    schema_builder.add_text_field("pos", STRING | STORED);
+    schema_builder.add_text_field("gender", STRING | STORED);
+    schema_builder.add_text_field("citation", STRING); // bibl n attributes and their CTS URNs, for finding entries citing a passage
+    schema_builder.build()
+}
 
-    let index = Index::create_in_dir(index_path, schema.clone())?;
-    let en_stem_analyzer = TextAnalyzer::builder(SimpleTokenizer::default())
-        //.filter(StopWordFilter::new(Language::Greek))
-        .filter(LowerCaser)
-        .filter(NoDiacritcs)
-        .filter(Stemmer::new(Language::English))
-        .build();
+// tokenizers are not saved with the index, so they must be registered each time it is opened
+fn register_tokenizers(index: &Index) {
+    let tokenizers = index.tokenizers();
+    // definitions are tokenized before they are added, so this analyzes queries on them
+    tokenizers.register(DEFINITION_TOKENIZER, definition_analyzer());
+    tokenizers.register(ENGLISH_TOKENIZER, english_analyzer());
+    tokenizers.register(GREEK_TOKENIZER, greek_analyzer());
+    tokenizers.register(LATIN_TOKENIZER, latin_analyzer());
+    tokenizers.register(LEMMA_TOKENIZER, lemma_analyzer());
+    tokenizers.register(LEMMA_PREFIX_TOKENIZER, lemma_prefix_analyzer());
+}
 
-    index.tokenizers().register("el_stem", en_stem_analyzer);
+fn create_index(index_path: &Path, positions: bool) -> anyhow::Result<Index> {
+    if index_path.is_dir() {
+        fs::remove_dir_all(index_path)?;
+    }
+    fs::create_dir(index_path)?;
 
-    // let index = Index::create_in_ram(schema.clone());
-    let index_writer: IndexWriter = index.writer(50_000_000)?;
+    let index = Index::create_in_dir(index_path, build_schema(positions))?;
+    register_tokenizers(&index);
+    Ok(index)
+}
 
-    install_default_drivers();
-    let conn = AnyConnection::connect("sqlite://dbv3.sqlite?mode=rwc").await?;
+fn open_index(index_path: &Path) -> anyhow::Result<Index> {
+    let index = Index::open_in_dir(index_path)?;
+    register_tokenizers(&index);
+    Ok(index)
+}
+
+async fn build(args: &Args, registry: &[Lexicon], lexica: Vec<Lexicon>) -> anyhow::Result<()> {
+    if Path::new(OUTPUT).is_file() {
+        fs::remove_file(OUTPUT).expect("File delete failed");
+    }
+
+    // the index and sqlite db are built beside the live ones and only moved into place once the
+    // build is complete, so a failed build leaves the live ones as they were
+    let staged_index = if args.command == Command::Load {
+        None
+    } else {
+        Some(StagedPath::new(&args.index_path)?)
+    };
+    let staged_db = if args.command == Command::Index || args.db_url.is_some() {
+        None // a database server is loaded in place
+    } else {
+        Some(StagedPath::new(&args.db_path)?)
+    };
+
+    // an index-only build is always a full one, as only a db load records which entries came from
+    // which files; it takes its word ids from the seqs of the live db instead of counting them
+    let mut incremental = !args.full && args.command != Command::Index;
+    let left_out: Vec<&str> = registry
+        .iter()
+        .filter(|lex| lex.enabled && !lexica.iter().any(|l| l.name == lex.name))
+        .map(|lex| lex.name.as_str())
+        .collect();
+    if !incremental {
+        ensure_none_left_out(&left_out)?;
+    }
+    let numbering_db = if args.command == Command::Index {
+        if args.db_url.is_none() && !args.db_path.exists() {
+            anyhow::bail!(
+                "{} does not exist; the index takes its word ids from the db, so load it first",
+                args.db_path.display()
+            );
+        }
+        let mut db = AnyConnection::connect(&args.db_url()).await?;
+        if migrations::schema_version(&mut db).await.ok() != Some(migrations::SCHEMA_VERSION) {
+            anyhow::bail!(
+                "the index takes its word ids from the db, so load it with this version first"
+            );
+        }
+        Some(db)
+    } else {
+        None
+    };
+
+    let index_writer = match &staged_index {
+        Some(staged) => {
+            let mut index = None;
+            if incremental && staged.copy_live()? {
+                // an index with other fields has to be built again from scratch
+                index = open_index(&staged.path())
+                    .ok()
+                    .filter(|index| index.schema() == build_schema(args.positions));
+            }
+            let index = match index {
+                Some(index) => index,
+                None => {
+                    incremental = false;
+                    create_index(&staged.path(), args.positions)?
+                }
+            };
+            // let index = Index::create_in_ram(schema.clone());
+            Some(index.writer(50_000_000)?)
+        }
+        None => None,
+    };
+    if let Some(staged) = &staged_db
+        && incremental
+        && !staged.copy_live()?
+    {
+        incremental = false;
+    }
 
-    let unique_hashmap = HashMap::new();
+    let db = if args.command == Command::Index {
+        None
+    } else {
+        let url = match &staged_db {
+            Some(staged) => cli::sqlite_url(&staged.path()),
+            None => args.db_url(),
+        };
+        Some(AnyConnection::connect(&url).await?)
+    };
 
+    let index_fields = match &index_writer {
+        Some(index_writer) => Some(IndexFields::new(&index_writer.index().schema())?),
+        None => None,
+    };
     let mut processor = Processor {
-        lexica: vec![lsj, ls /* , slater */],
+        lexica,
         index_writer,
-        db: conn,
-        unique_hashmap,
+        index_fields,
+        db,
+        pull: args.pull,
+        incremental,
+        diagnostics: Diagnostics::new(args.max_errors),
+        unknown_elements: BTreeMap::new(),
+        unparsed_citations: BTreeSet::new(),
+        unknown_authors: BTreeMap::new(),
+        homographs: Homographs::default(),
+        reloaded: HashMap::new(),
+        registry: registry
+            .iter()
+            .filter(|lex| lex.enabled)
+            .map(|lex| lex.name.clone())
+            .collect(),
+        numbering_db,
     };
 
-    processor.start().await.unwrap();
+    processor.start().await?;
 
-    //let word_id_field = index.schema().get_field("word_id").unwrap();
-    //let lemma_field = index.schema().get_field("lemma").unwrap();
-    let lexicon_field = index.schema().get_field("lexicon").unwrap();
-    let definition_field = index.schema().get_field("definition").unwrap();
+    if !processor.unknown_elements.is_empty() {
+        println!("elements with no render rule:");
+        for (name, count) in &processor.unknown_elements {
+            println!("  <{}> {}", name, count);
+        }
+    }
+    if !processor.unparsed_citations.is_empty() {
+        println!(
+            "{} distinct bibl n values could not be parsed as CTS references, e.g.:",
+            processor.unparsed_citations.len()
+        );
+        for n in processor.unparsed_citations.iter().take(20) {
+            println!("  {}", n);
+        }
+    }
+    if !processor.unknown_authors.is_empty() {
+        println!("Author abbreviations not in the author lists:");
+        for (abbrev, count) in &processor.unknown_authors {
+            println!("  {} {}", abbrev, count);
+        }
+    }
 
+    processor.diagnostics.print_summary();
+    if processor.diagnostics.exceeded() {
+        anyhow::bail!(
+            "{} error(s) exceeded the limit of {} set by --max-errors",
+            processor.diagnostics.count(),
+            args.max_errors
+        );
+    }
+
+    let index = match processor.index_writer.take() {
+        Some(index_writer) => {
+            let index = index_writer.index().clone();
+            index_writer.wait_merging_threads()?;
+            Some(index)
+        }
+        None => None,
+    };
+    println!("Checking the new build...");
+    let db = processor.db.as_mut().or(processor.numbering_db.as_mut());
+    if !check_entries(db, index.as_ref(), &processor.lexica).await? {
+        anyhow::bail!("the new build is incomplete, so it was not put in place");
+    }
+    for db in [processor.db.take(), processor.numbering_db.take()]
+        .into_iter()
+        .flatten()
+    {
+        db.close().await?;
+    }
+    drop(index);
+
+    let staged: Vec<StagedPath> = staged_db.into_iter().chain(staged_index).collect();
+    staging::publish(&staged)?;
+    for s in &staged {
+        let backup = staging::backup_path(s.live());
+        if backup.exists() {
+            println!("previous build kept as {}", backup.display());
+        }
+    }
+    Ok(())
+}
+
+fn search(args: &Args, query: &str) -> anyhow::Result<()> {
+    let index = open_index(&args.index_path)?;
     let reader = index
         .reader_builder()
-        .reload_policy(ReloadPolicy::OnCommitWithDelay)
+        .reload_policy(ReloadPolicy::Manual)
         .try_into()?;
+    let request = SearchRequest {
+        query,
+        mode: args.search_mode,
+        lexica: &args.lexica,
+        offset: args.offset,
+        limit: args.limit,
+    };
+    let results = search::search(&reader.searcher(), &request)?;
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&results)?);
+    } else {
+        print_results(&results, args.offset);
+    }
+    Ok(())
+}
 
-    let searcher = reader.searcher();
-    let query_parser = QueryParser::for_index(
-        &index,
-        //this vector contains default fields used if field is not specified in query
-        vec![lexicon_field, definition_field],
+fn print_results(results: &SearchResults, offset: usize) {
+    for hit in &results.hits {
+        // matches are marked with *, and the snippet kept to one line
+        let mut snippet = String::new();
+        let mut last = 0;
+        for range in &hit.highlights {
+            snippet.push_str(&hit.snippet[last..range.start]);
+            snippet.push('*');
+            snippet.push_str(&hit.snippet[range.clone()]);
+            snippet.push('*');
+            last = range.end;
+        }
+        snippet.push_str(&hit.snippet[last..]);
+        println!(
+            "{:>8} {:<12} {:<24} {:>7.3}  {}",
+            hit.word_id,
+            hit.lexicon,
+            hit.lemma,
+            hit.score,
+            snippet.split_whitespace().collect::<Vec<_>>().join(" ")
+        );
+    }
+    let shown = match results.hits.len() {
+        0 => "no results".to_string(),
+        n => format!("results {}-{}", offset + 1, offset + n),
+    };
+    let lexica = results
+        .lexica
+        .iter()
+        .map(|(lexicon, count)| format!("{} {}", lexicon, count))
+        .collect::<Vec<_>>()
+        .join(", ");
+    println!("{} of {} (by lexicon: {})", shown, results.total, lexica);
+}
+
+/// Indexes each selected lexicon on its own, without and with positions for definitions, and
+/// reports the time taken and the size of each index.
+async fn bench(lexica: &[Lexicon]) -> anyhow::Result<()> {
+    let mut rows = Vec::new();
+    for lex in lexica {
+        let mut runs = Vec::new();
+        for positions in [false, true] {
+            let dir = tempfile::tempdir()?;
+            let index_path = dir.path().join("index");
+            let index = create_index(&index_path, positions)?;
+            let mut processor = Processor {
+                lexica: vec![lex.clone()],
+                index_writer: Some(index.writer(50_000_000)?),
+                index_fields: Some(IndexFields::new(&index.schema())?),
+                db: None,
+                pull: false,
+                incremental: false,
+                diagnostics: Diagnostics::new(usize::MAX),
+                unknown_elements: BTreeMap::new(),
+                unparsed_citations: BTreeSet::new(),
+                unknown_authors: BTreeMap::new(),
+                homographs: Homographs::default(),
+                reloaded: HashMap::new(),
+                registry: BTreeSet::new(),
+                numbering_db: None,
+            };
+            let started = Instant::now();
+            processor.start().await?;
+            if let Some(index_writer) = processor.index_writer.take() {
+                index_writer.wait_merging_threads()?;
+            }
+            let elapsed = started.elapsed();
+            let entries = index.reader()?.searcher().num_docs();
+            runs.push((entries, elapsed, dir_size(&index_path)?));
+        }
+        rows.push((lex.name.clone(), runs));
+    }
+
+    println!(
+        "\n{:<16} {:>8} {:>10} {:>10} {:>12} {:>12} {:>8}",
+        "lexicon", "entries", "time", "size", "time (pos)", "size (pos)", "size +"
     );
+    let mb = |bytes: u64| format!("{:.1} MB", bytes as f64 / 1_000_000.0);
+    for (name, runs) in rows {
+        let (entries, time, size) = runs[0];
+        let (_, time_pos, size_pos) = runs[1];
+        println!(
+            "{:<16} {:>8} {:>9.1}s {:>10} {:>11.1}s {:>12} {:>7.0}%",
+            name,
+            entries,
+            time.as_secs_f64(),
+            mb(size),
+            time_pos.as_secs_f64(),
+            mb(size_pos),
+            (size_pos as f64 / size.max(1) as f64 - 1.0) * 100.0
+        );
+    }
+    Ok(())
+}
 
-    match query_parser.parse_query("carry AND (lexicon:slater OR lexicon:lewisshort)") {
-        Ok(query) => match searcher.search(&query, &TopDocs::with_limit(100)) {
-            Ok(top_docs) => {
-                for (_score, doc_address) in top_docs {
-                    match searcher.doc::<TantivyDocument>(doc_address) {
-                        Ok(_retrieved_doc) => println!("success"), //println!("{}", schema.to_json(&retrieved_doc)),
-                        Err(e) => println!("Error retrieving document: {:?}", e),
-                    }
-                }
+// total size of the files in a directory
+fn dir_size(path: &Path) -> io::Result<u64> {
+    let mut size = 0;
+    for entry in fs::read_dir(path)? {
+        let metadata = entry?.metadata()?;
+        if metadata.is_file() {
+            size += metadata.len();
+        }
+    }
+    Ok(size)
+}
+
+/// Checks that every selected lexicon has entries and that the db and index hold the same number of them.
+async fn verify(args: &Args, lexica: &[Lexicon]) -> anyhow::Result<()> {
+    let mut db = AnyConnection::connect(&args.db_url()).await?;
+    let index = open_index(&args.index_path)?;
+    if !check_entries(Some(&mut db), Some(&index), lexica).await? {
+        anyhow::bail!("verification failed");
+    }
+    Ok(())
+}
+
+/// Prints the number of entries of each lexicon in the db and index, and returns whether every
+/// lexicon has entries and, if both are given, the same entries in each with each entry's seq in
+/// the db as its word id in the index.
+async fn check_entries(
+    mut db: Option<&mut AnyConnection>,
+    index: Option<&Index>,
+    lexica: &[Lexicon],
+) -> anyhow::Result<bool> {
+    let searcher = match index {
+        Some(index) => Some(index.reader()?.searcher()),
+        None => None,
+    };
+
+    let mut ok = true;
+    for lex in lexica {
+        let db_ids = match db.as_deref_mut() {
+            Some(db) => Some(db_word_ids(db, &lex.name).await?),
+            None => None,
+        };
+        let index_ids = match &searcher {
+            Some(searcher) => Some(index_word_ids(searcher, &lex.name)?),
+            None => None,
+        };
+
+        let status = match (&db_ids, &index_ids) {
+            (Some(ids), _) | (_, Some(ids)) if ids.is_empty() => "empty".to_string(),
+            (Some(db_ids), Some(index_ids)) if db_ids.len() != index_ids.len() => {
+                "mismatch".to_string()
             }
-            Err(e) => println!("Error searching tantivy index: {:?}", e),
-        },
+            (Some(db_ids), Some(index_ids)) if db_ids != index_ids => format!(
+                "mismatch: {} entries have other ids in the index",
+                db_ids.difference(index_ids).count()
+            ),
+            _ => "ok".to_string(),
+        };
+        if status != "ok" {
+            ok = false;
+        }
+        let show = |ids: &Option<BTreeSet<(String, u64)>>| {
+            ids.as_ref()
+                .map_or("-".to_string(), |ids| ids.len().to_string())
+        };
+        println!(
+            "{:<12} db: {:>8} index: {:>8} {}",
+            lex.name,
+            show(&db_ids),
+            show(&index_ids),
+            status
+        );
+    }
+    Ok(ok)
+}
+
+// the entry id and seq of each of a lexicon's entries in the db
+async fn db_word_ids(
+    db: &mut AnyConnection,
+    lexicon: &str,
+) -> anyhow::Result<BTreeSet<(String, u64)>> {
+    let query = Dialect::of(db).sql("SELECT entry_id, seq FROM words WHERE lexicon = $1;");
+    let rows: Vec<(String, i32)> = sqlx::query_as(&query).bind(lexicon).fetch_all(db).await?;
+    Ok(rows
+        .into_iter()
+        .map(|(entry_id, seq)| (entry_id, seq as u64))
+        .collect())
+}
+
+// the entry id and word id of each of a lexicon's documents in the index
+fn index_word_ids(searcher: &Searcher, lexicon: &str) -> anyhow::Result<BTreeSet<(String, u64)>> {
+    let fields = IndexFields::new(searcher.schema())?;
+    let query = TermQuery::new(
+        Term::from_field_text(fields.lexicon, lexicon),
+        IndexRecordOption::Basic,
+    );
+    let mut ids = BTreeSet::new();
+    for address in searcher.search(&query, &DocSetCollector)? {
+        let doc: TantivyDocument = searcher.doc(address)?;
+        let entry_id = doc.get_first(fields.entry_id).and_then(|v| v.as_str());
+        let word_id = doc.get_first(fields.word_id).and_then(|v| v.as_u64());
+        ids.insert((
+            entry_id.unwrap_or_default().to_string(),
+            word_id.unwrap_or_default(),
+        ));
+    }
+    Ok(ids)
+}
+
+// refuses a build that starts again from nothing when it would drop lexica --lexicon leaves out
+fn ensure_none_left_out(left_out: &[&str]) -> anyhow::Result<()> {
+    if !left_out.is_empty() {
+        anyhow::bail!(
+            "this build reloads every lexicon, so it would drop {}, which --lexicon leaves out; \
+             run it without --lexicon",
+            left_out.join(", ")
+        );
+    }
+    Ok(())
+}
+
+// the highest seq in the db, 0 if it has no entries
+async fn max_seq(db: &mut AnyConnection) -> anyhow::Result<i32> {
+    let max_seq: i64 = sqlx::query_scalar("SELECT COALESCE(MAX(seq), 0) FROM words;")
+        .fetch_one(db)
+        .await?;
+    Ok(max_seq as i32)
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let args = match Args::parse() {
+        Ok(args) => args,
         Err(e) => {
-            println!("Query parsing error: {:?}", e);
+            eprintln!("error: {:#}\n\n{}", e, cli::USAGE);
+            std::process::exit(2);
         }
+    };
+    if args.command == Command::Help {
+        println!("{}", cli::USAGE);
+        return Ok(());
+    }
+
+    let registry = config::load_lexica(&args.registry)?;
+    let lexica = config::select_lexica(registry.clone(), &args.lexica)?;
+
+    install_default_drivers();
+
+    match &args.command {
+        Command::Fetch => {
+            for lex in &lexica {
+                fetch_lexicon(lex);
+            }
+        }
+        Command::Build | Command::Load | Command::Index => build(&args, &registry, lexica).await?,
+        Command::Search { query } => search(&args, query)?,
+        Command::Verify => verify(&args, &lexica).await?,
+        Command::Bench => bench(&lexica).await?,
+        Command::Rollback => {
+            let mut live = vec![args.index_path.as_path()];
+            if args.db_url.is_none() {
+                live.push(args.db_path.as_path());
+            }
+            live.retain(|path| staging::backup_path(path).exists());
+            if live.is_empty() {
+                anyhow::bail!("there is no previous build to roll back to");
+            }
+            for path in live {
+                staging::rollback(path)?;
+                println!("rolled back {}", path.display());
+            }
+        }
+        Command::Help => (),
     }
 
     Ok(())
@@ -769,81 +2129,258 @@ fn do_merge<'a>(
     Ok(())
 }
 
-use std::mem;
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unnumbered_homographs_come_after_reserved_ones() {
+        let mut homographs = Homographs::default();
+        homographs.reserve("λύω", 2);
+        assert_eq!(homographs.number("λύω", None), 3);
+        assert_eq!(homographs.number("λύω", Some(2)), 2);
+        assert_eq!(homographs.number("λύω", Some(1)), 1);
+        // a number given twice goes to the first entry only
+        assert_eq!(homographs.number("λύω", Some(2)), 4);
+        assert_eq!(homographs.number("λύω", [2, 3]), 5);
+        assert_eq!(homographs.number("γῆ", None), 1);
+    }
+
+    fn entries(ids: &[&str]) -> String {
+        let mut xml = String::from("<TEI.2><text><body>\n");
+        for id in ids {
+            xml += &format!(
+                "<div2 id=\"{id}\" type=\"main\"><head>λύω</head>, <orth>λούω</orth> <sense n=\"A\" level=\"1\">{id}, <bibl n=\"Perseus:abo:tlg,0012,001:1:1\"><author>Hom.</author> Il. 1.1</bibl></sense></div2>\n"
+            );
+        }
+        xml + "</body></text></TEI.2>\n"
+    }
 
-use tantivy::tokenizer::{Token, TokenFilter, TokenStream, Tokenizer};
+    fn commit(repo: &Repository, files: &[(&str, &[&str])]) {
+        let dir = repo.workdir().unwrap();
+        for (file, ids) in files {
+            fs::write(dir.join(file), entries(ids)).unwrap();
+        }
+        let mut staged = repo.index().unwrap();
+        staged
+            .add_all(["*"], git2::IndexAddOption::DEFAULT, None)
+            .unwrap();
+        staged.write().unwrap();
+        let tree = repo.find_tree(staged.write_tree().unwrap()).unwrap();
+        let signature = git2::Signature::now("test", "test@example.com").unwrap();
+        let parent = repo.head().ok().map(|head| head.peel_to_commit().unwrap());
+        repo.commit(
+            Some("HEAD"),
+            &signature,
+            &signature,
+            "test",
+            &tree,
+            parent.iter().collect::<Vec<_>>().as_slice(),
+        )
+        .unwrap();
+    }
 
-/// Token filter that removes diacritics from terms.
-#[derive(Clone)]
-pub struct NoDiacritcs;
+    // a git repository in dir for a lexicon of the given name, with a registry listing it
+    fn test_lexicon(dir: &Path, name: &str) -> Repository {
+        let registry = serde_json::json!({ "lexica": [{
+            "name": name,
+            "display_name": "Test",
+            "repo_url": "file:///nowhere",
+            "dir_name": dir.join("lex"),
+            "file_glob": "*.xml",
+            "pull": false,
+        }] });
+        fs::write(dir.join("lexica.json"), registry.to_string()).unwrap();
+        Repository::init(dir.join("lex")).unwrap()
+    }
 
-impl TokenFilter for NoDiacritcs {
-    type Tokenizer<T: Tokenizer> = DiacriticFilter<T>;
+    // runs a command on the lexica in dir's registry, with the db and index in dir unless the
+    // options say otherwise
+    async fn run(dir: &Path, options: &[&str]) -> anyhow::Result<Args> {
+        let path = |name: &str| dir.join(name).to_string_lossy().into_owned();
+        let mut args = vec![
+            "--registry".to_string(),
+            path("lexica.json"),
+            "--db".to_string(),
+            path("db.sqlite"),
+            "--index".to_string(),
+            path("index"),
+            "--no-pull".to_string(),
+        ];
+        args.extend(options.iter().map(|option| option.to_string()));
+        let args = Args::parse_from(args)?;
+        let registry = config::load_lexica(&args.registry)?;
+        let lexica = config::select_lexica(registry.clone(), &args.lexica)?;
+        build(&args, &registry, lexica).await?;
+        Ok(args)
+    }
 
-    fn transform<T: Tokenizer>(self, tokenizer: T) -> Self::Tokenizer<T> {
-        DiacriticFilter {
-            tokenizer,
-            buffer: String::new(),
+    // runs the command on the test lexicon, and returns each entry's seq in the db after checking
+    // that it is the entry's word id in the index, unless only the db was loaded
+    async fn build_and_compare(dir: &Path, command: &str) -> BTreeSet<(String, u64)> {
+        let args = run(dir, &[command]).await.unwrap();
+        let mut db = AnyConnection::connect(&args.db_url()).await.unwrap();
+        let db_ids = db_word_ids(&mut db, "test").await.unwrap();
+        if args.command != Command::Load {
+            let index = open_index(&args.index_path).unwrap();
+            let searcher = index.reader().unwrap().searcher();
+            assert_eq!(db_ids, index_word_ids(&searcher, "test").unwrap());
         }
+        db_ids
     }
-}
 
-#[derive(Clone)]
-pub struct DiacriticFilter<T> {
-    tokenizer: T,
-    buffer: String,
-}
+    #[tokio::test]
+    async fn reloaded_entries_keep_their_seq_as_word_id() {
+        install_default_drivers();
+        let dir = tempfile::tempdir().unwrap();
+        let repo = test_lexicon(dir.path(), "test");
+        commit(&repo, &[("a.xml", &["a1", "a2"]), ("b.xml", &["b1"])]);
+        let first = build_and_compare(dir.path(), "build").await;
+        let seq = |ids: &BTreeSet<(String, u64)>, id: &str| {
+            ids.iter()
+                .find(|(entry_id, _)| entry_id == id)
+                .map(|(_, seq)| *seq)
+        };
 
-impl<T: Tokenizer> Tokenizer for DiacriticFilter<T> {
-    type TokenStream<'a> = DiacriticTokenStream<'a, T::TokenStream<'a>>;
+        // a1 goes, a3 and c1 come, and a2 and b1 stay
+        commit(&repo, &[("a.xml", &["a2", "a3"]), ("c.xml", &["c1"])]);
+        let second = build_and_compare(dir.path(), "build").await;
+        assert_eq!(second.len(), 4);
+        assert_eq!(seq(&second, "a1"), None);
+        assert_eq!(seq(&second, "a2"), seq(&first, "a2"));
+        assert_eq!(seq(&second, "b1"), seq(&first, "b1"));
+        assert!(seq(&second, "a3") > Some(3) && seq(&second, "c1") > Some(3));
+
+        // an index built after an incremental load of the db alone takes the db's seqs
+        commit(&repo, &[("c.xml", &["c2", "c1"])]);
+        let third = build_and_compare(dir.path(), "load").await;
+        assert_eq!(seq(&third, "c1"), seq(&second, "c1"));
+        assert_eq!(build_and_compare(dir.path(), "index").await, third);
+    }
 
-    fn token_stream<'a>(&'a mut self, text: &'a str) -> Self::TokenStream<'a> {
-        self.buffer.clear();
-        DiacriticTokenStream {
-            tail: self.tokenizer.token_stream(text),
-            buffer: &mut self.buffer,
+    // loads a lexicon into the database at url, which is emptied first, then renames it so that
+    // the old name's rows are removed
+    async fn backend_round_trip(url: &str) {
+        install_default_drivers();
+        let dir = tempfile::tempdir().unwrap();
+        let repo = test_lexicon(dir.path(), "test");
+        commit(&repo, &[("a.xml", &["a1", "a2"])]);
+        run(dir.path(), &["--db-url", url, "--full", "build"])
+            .await
+            .unwrap();
+
+        let mut db = AnyConnection::connect(url).await.unwrap();
+        assert_eq!(
+            migrations::schema_version(&mut db).await.unwrap(),
+            migrations::SCHEMA_VERSION
+        );
+        let record = BuildRecord::read_db(&mut db).await.unwrap();
+        let head = repo.head().unwrap().target().unwrap().to_string();
+        assert_eq!(record.lexica, ["test"]);
+        assert_eq!(record.commits.get("test"), Some(&head));
+        let mut rows = Vec::new();
+        for table in migrations::DATA_TABLES {
+            let query = format!("SELECT COUNT(*) FROM {};", table);
+            let count: i64 = sqlx::query_scalar(&query).fetch_one(&mut db).await.unwrap();
+            assert!(count > 0, "{} is empty", table);
+            rows.push(count);
         }
+
+        test_lexicon(dir.path(), "renamed");
+        run(dir.path(), &["--db-url", url, "build"]).await.unwrap();
+        assert!(db_word_ids(&mut db, "test").await.unwrap().is_empty());
+        assert_eq!(db_word_ids(&mut db, "renamed").await.unwrap().len(), 2);
+        for (table, count) in migrations::DATA_TABLES.iter().zip(rows) {
+            let query = format!("SELECT COUNT(*) FROM {};", table);
+            let now: i64 = sqlx::query_scalar(&query).fetch_one(&mut db).await.unwrap();
+            assert_eq!(
+                now, count,
+                "{} still has rows of the removed lexicon",
+                table
+            );
+        }
+        let record = BuildRecord::read_db(&mut db).await.unwrap();
+        assert_eq!(record.lexica, ["renamed"]);
     }
-}
 
-pub struct DiacriticTokenStream<'a, T> {
-    buffer: &'a mut String,
-    tail: T,
-}
+    // a registry of two lexica in one repository, with b enabled or not
+    fn two_lexica(dir: &Path, b_enabled: bool) {
+        let lexicon = |name: &str, enabled| {
+            serde_json::json!({
+                "name": name,
+                "display_name": name,
+                "repo_url": "file:///nowhere",
+                "dir_name": dir.join("lex"),
+                "file_glob": format!("{name}*.xml"),
+                "pull": false,
+                "enabled": enabled,
+            })
+        };
+        let registry =
+            serde_json::json!({ "lexica": [lexicon("a", true), lexicon("b", b_enabled)] });
+        fs::write(dir.join("lexica.json"), registry.to_string()).unwrap();
+    }
 
-// writes a lowercased version of text into output.
-fn to_diacritic_free_unicode(text: &str, output: &mut String) {
-    output.clear();
-    output.reserve(50);
-    // for c in text.chars() {
-    //     // Contrary to the std, we do not take care of sigma special case.
-    //     // This will have an normalizationo effect, which is ok for search.
-    //     output.extend(c.to_lowercase());
-    // }
-    let stripped = hgk_strip_diacritics(text, 0xFFFFFFFF);
-    output.push_str(&stripped);
-}
+    #[tokio::test]
+    async fn lexica_left_out_are_kept_and_disabled_ones_removed() {
+        install_default_drivers();
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init(dir.path().join("lex")).unwrap();
+        commit(&repo, &[("a1.xml", &["a1"]), ("b1.xml", &["b1", "b2"])]);
+        two_lexica(dir.path(), true);
+        let args = run(dir.path(), &["build"]).await.unwrap();
+        let mut db = AnyConnection::connect(&args.db_url()).await.unwrap();
+        let count = async |db: &mut AnyConnection, name| db_word_ids(db, name).await.unwrap().len();
+        assert_eq!(count(&mut db, "b").await, 2);
+
+        commit(&repo, &[("a1.xml", &["a1", "a2"])]);
+        run(dir.path(), &["--lexicon", "a", "build"]).await.unwrap();
+        let mut db = AnyConnection::connect(&args.db_url()).await.unwrap();
+        assert_eq!(count(&mut db, "a").await, 2);
+        assert_eq!(count(&mut db, "b").await, 2);
+
+        // a full build or an index build would drop b
+        assert!(
+            run(dir.path(), &["--lexicon", "a", "--full", "build"])
+                .await
+                .is_err()
+        );
+        assert!(run(dir.path(), &["--lexicon", "a", "index"]).await.is_err());
+        let mut db = AnyConnection::connect(&args.db_url()).await.unwrap();
+        assert_eq!(count(&mut db, "b").await, 2);
+
+        two_lexica(dir.path(), false);
+        run(dir.path(), &["build"]).await.unwrap();
+        let mut db = AnyConnection::connect(&args.db_url()).await.unwrap();
+        assert_eq!(count(&mut db, "a").await, 2);
+        assert_eq!(count(&mut db, "b").await, 0);
+        let index = open_index(&args.index_path).unwrap();
+        let searcher = index.reader().unwrap().searcher();
+        assert!(index_word_ids(&searcher, "b").unwrap().is_empty());
+        let record = BuildRecord::read_db(&mut db).await.unwrap();
+        assert_eq!(record.lexica, ["a"]);
+    }
 
-impl<T: TokenStream> TokenStream for DiacriticTokenStream<'_, T> {
-    fn advance(&mut self) -> bool {
-        if !self.tail.advance() {
-            return false;
-        }
-        // if self.token_mut().text.is_ascii() {
-        //     // fast track for ascii.
-        //     self.token_mut().text.make_ascii_lowercase();
-        // } else {
-            to_diacritic_free_unicode(&self.tail.token().text, self.buffer);
-            mem::swap(&mut self.tail.token_mut().text, self.buffer);
-            //}
-        true
+    #[tokio::test]
+    async fn sqlite_backend() {
+        let dir = tempfile::tempdir().unwrap();
+        backend_round_trip(&cli::sqlite_url(&dir.path().join("db.sqlite"))).await;
     }
 
-    fn token(&self) -> &Token {
-        self.tail.token()
+    // these need a scratch database, which they empty: run them with
+    // TEST_POSTGRES_URL=postgres://... TEST_MYSQL_URL=mysql://... cargo test -- --ignored
+
+    #[tokio::test]
+    #[ignore = "needs a postgres database in TEST_POSTGRES_URL"]
+    async fn postgres_backend() {
+        let url = std::env::var("TEST_POSTGRES_URL").expect("TEST_POSTGRES_URL is not set");
+        backend_round_trip(&url).await;
     }
 
-    fn token_mut(&mut self) -> &mut Token {
-        self.tail.token_mut()
+    #[tokio::test]
+    #[ignore = "needs a mysql database in TEST_MYSQL_URL"]
+    async fn mysql_backend() {
+        let url = std::env::var("TEST_MYSQL_URL").expect("TEST_MYSQL_URL is not set");
+        backend_round_trip(&url).await;
     }
 }
@@ -0,0 +1,120 @@
+use anyhow::{Context, bail};
+use sqlx::{AnyConnection, Connection};
+
+use crate::dialect::Dialect;
+
+/// Numbered schema migrations, applied in order; a database's schema version is the number of
+/// migrations that have been applied to it. Add new migrations at the end, and never change one
+/// that has been released. Statements are written as described in Dialect.
+static MIGRATIONS: &[&[&str]] = &[
+    // 1: the words table and the tables extracted from entries. Databases from before there was a
+    // meta table have no version; their tables were rebuilt by every load, so they are dropped.
+    &[
+        "DROP TABLE IF EXISTS words;",
+        "DROP TABLE IF EXISTS senses;",
+        "DROP TABLE IF EXISTS citations;",
+        "DROP TABLE IF EXISTS authors;",
+        "DROP TABLE IF EXISTS variants;",
+        "CREATE TABLE words (seq INTEGER PRIMARY KEY, lexicon {key}, word {key}, homograph INTEGER, entry_id {key}, source_file TEXT, source_line INTEGER, sortword {key}, def {text}, pos TEXT, gender TEXT, itype TEXT, etym TEXT, senses {text}){strict};",
+        "CREATE INDEX lexicon_idx ON words (lexicon);",
+        "CREATE INDEX sortword_idx ON words (sortword);",
+        "CREATE INDEX word_idx ON words (word, homograph);",
+        "CREATE INDEX entry_id_idx ON words (lexicon, entry_id);",
+        "CREATE TABLE senses (seq INTEGER, ord INTEGER, path {key}, level INTEGER, n TEXT, text TEXT, PRIMARY KEY (seq, ord)){strict};",
+        "CREATE INDEX sense_path_idx ON senses (seq, path);",
+        "CREATE TABLE citations (seq INTEGER, ord INTEGER, n {key}, text TEXT, sense TEXT, urn {key}, author {key}, work {key}, passage TEXT, PRIMARY KEY (seq, ord)){strict};",
+        "CREATE INDEX citation_n_idx ON citations (n);",
+        "CREATE INDEX citation_urn_idx ON citations (urn);",
+        "CREATE INDEX citation_work_idx ON citations (author, work);",
+        "CREATE TABLE variants (seq INTEGER, ord INTEGER, orth {key}, PRIMARY KEY (seq, ord)){strict};",
+        "CREATE INDEX variant_orth_idx ON variants (orth);",
+        "CREATE TABLE authors (seq INTEGER, ord INTEGER, abbrev {key}, name TEXT, author_id {key}, PRIMARY KEY (seq, ord)){strict};",
+        "CREATE INDEX author_id_idx ON authors (author_id);",
+        "CREATE INDEX author_abbrev_idx ON authors (abbrev);",
+    ],
+    // 2: homograph numbers are unique for each headword in a lexicon. A load after a migration
+    // replaces all the rows anyway, so they are dropped here rather than renumbered.
+    &[
+        "DELETE FROM words;",
+        "DELETE FROM senses;",
+        "DELETE FROM citations;",
+        "DELETE FROM authors;",
+        "DELETE FROM variants;",
+        "CREATE UNIQUE INDEX homograph_idx ON words (lexicon, word, homograph);",
+    ],
+];
+
+/// The schema version this loader writes.
+pub const SCHEMA_VERSION: usize = MIGRATIONS.len();
+
+/// Tables whose rows are replaced by every load.
+pub static DATA_TABLES: [&str; 5] = ["words", "senses", "citations", "variants", "authors"];
+
+/// Brings the database up to SCHEMA_VERSION, refusing to touch one written by a newer loader.
+/// Returns whether any migrations were applied.
+pub async fn migrate(db: &mut AnyConnection) -> anyhow::Result<bool> {
+    let dialect = Dialect::of(db);
+    let query = dialect.ddl(
+        r#"CREATE TABLE IF NOT EXISTS meta ("key" {key} PRIMARY KEY, value TEXT NOT NULL){strict};"#,
+    );
+    sqlx::query(&query)
+        .execute(&mut *db)
+        .await
+        .context("could not create meta table")?;
+
+    let version = schema_version(db).await?;
+    if version > SCHEMA_VERSION {
+        bail!(
+            "database schema version {} is newer than the version {} this loader supports; use a newer loader",
+            version,
+            SCHEMA_VERSION
+        );
+    }
+
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        let mut tx = db.begin().await?;
+        for statement in migration.iter() {
+            sqlx::query(&dialect.ddl(statement))
+                .execute(&mut *tx)
+                .await
+                .with_context(|| format!("schema migration {} failed", i + 1))?;
+        }
+        set_meta(&mut tx, "schema_version", &(i + 1).to_string()).await?;
+        tx.commit().await?;
+        println!("migrated database schema to version {}", i + 1);
+    }
+    Ok(version < SCHEMA_VERSION)
+}
+
+/// Number of migrations applied to the database, 0 if it has none.
+pub async fn schema_version(db: &mut AnyConnection) -> anyhow::Result<usize> {
+    match get_meta(db, "schema_version").await? {
+        Some(version) => version
+            .parse()
+            .with_context(|| format!("invalid schema_version \"{}\" in meta table", version)),
+        None => Ok(0),
+    }
+}
+
+pub async fn get_meta(db: &mut AnyConnection, key: &str) -> anyhow::Result<Option<String>> {
+    let query = Dialect::of(db).sql(r#"SELECT value FROM meta WHERE "key" = $1;"#);
+    let value = sqlx::query_scalar(&query)
+        .bind(key)
+        .fetch_optional(&mut *db)
+        .await?;
+    Ok(value)
+}
+
+pub async fn set_meta(db: &mut AnyConnection, key: &str, value: &str) -> anyhow::Result<()> {
+    let dialect = Dialect::of(db);
+    sqlx::query(&dialect.sql(r#"DELETE FROM meta WHERE "key" = $1;"#))
+        .bind(key)
+        .execute(&mut *db)
+        .await?;
+    sqlx::query(&dialect.sql(r#"INSERT INTO meta ("key", value) VALUES ($1, $2);"#))
+        .bind(key)
+        .bind(value)
+        .execute(&mut *db)
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,208 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, bail};
+use serde::Deserialize;
+
+use crate::html::{push_escaped_attr, push_escaped_text};
+
+// the markup used when a lexicon does not override it
+static DEFAULT_RULES: &str = include_str!("render_rules.json");
+
+// html elements that have no content or closing tag
+static VOID_ELEMENTS: [&str; 4] = ["br", "hr", "img", "wbr"];
+
+/// Maps a TEI element to the html written for it in a definition.
+#[derive(Clone, Debug, Deserialize)]
+pub struct RenderRule {
+    pub element: String,
+    /// attribute values the element must have for the rule to apply; "*" matches any value
+    #[serde(default)]
+    pub when: BTreeMap<String, String>,
+    /// if set, the rule only applies to empty (self-closing) elements, or only to non-empty ones
+    pub empty: Option<bool>,
+    /// html element to write, or none to keep the content without markup
+    pub output: Option<String>,
+    pub class: Option<String>,
+    /// html attributes to copy from TEI attributes: html name -> TEI name
+    #[serde(default)]
+    pub attributes: BTreeMap<String, String>,
+    /// text to write after the opening tag, with {name} replaced by the value of TEI attribute
+    /// name, e.g. a page number for <pb n="12"/>
+    pub text: Option<String>,
+}
+
+impl RenderRule {
+    fn matches(&self, element: &str, attrs: &[(String, String)], empty: bool) -> bool {
+        self.element == element
+            && self.empty.is_none_or(|e| e == empty)
+            && self.when.iter().all(|(name, want)| {
+                attrs
+                    .iter()
+                    .any(|(key, value)| key == name && (want == "*" || value == want))
+            })
+    }
+
+    /// Writes the opening tag to `output` and returns the matching closing tag.
+    pub fn open(&self, attrs: &[(String, String)], output: &mut String) -> String {
+        let Some(element) = &self.output else {
+            return String::new();
+        };
+        output.push('<');
+        output.push_str(element);
+        if let Some(class) = &self.class {
+            output.push_str(r#" class=""#);
+            push_escaped_attr(output, class);
+            output.push('"');
+        }
+        for (html_name, tei_name) in &self.attributes {
+            if let Some((_, value)) = attrs.iter().find(|(key, _)| key == tei_name) {
+                output.push(' ');
+                output.push_str(html_name);
+                output.push_str(r#"=""#);
+                push_escaped_attr(output, value);
+                output.push('"');
+            }
+        }
+        if VOID_ELEMENTS.contains(&element.as_str()) {
+            output.push_str("/>");
+            self.push_text(attrs, output);
+            return String::new();
+        }
+        output.push('>');
+        self.push_text(attrs, output);
+        format!("</{}>", element)
+    }
+
+    fn push_text(&self, attrs: &[(String, String)], output: &mut String) {
+        let Some(template) = &self.text else {
+            return;
+        };
+        let mut rest = template.as_str();
+        while let Some(start) = rest.find('{') {
+            let Some(len) = rest[start..].find('}') else {
+                break;
+            };
+            push_escaped_text(output, &rest[..start]);
+            let name = &rest[start + 1..start + len];
+            if let Some((_, value)) = attrs.iter().find(|(key, _)| key == name) {
+                push_escaped_text(output, value);
+            }
+            rest = &rest[start + len + 1..];
+        }
+        push_escaped_text(output, rest);
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        if self.element.is_empty() {
+            bail!("render rule is missing element");
+        }
+        let names = self
+            .output
+            .iter()
+            .chain(self.attributes.keys())
+            .map(String::as_str);
+        for name in names {
+            if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+                bail!(
+                    "render rule for <{}> has invalid html name \"{}\"",
+                    self.element,
+                    name
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The render rules for one lexicon: its own rules, if any, followed by the defaults.
+pub struct Renderer {
+    rules: Vec<RenderRule>,
+}
+
+impl Renderer {
+    pub fn load(path: Option<&Path>) -> anyhow::Result<Renderer> {
+        let mut rules = Vec::new();
+        if let Some(path) = path {
+            let json = fs::read_to_string(path)
+                .with_context(|| format!("could not read render rules {}", path.display()))?;
+            rules = serde_json::from_str(&json)
+                .with_context(|| format!("could not parse render rules {}", path.display()))?;
+        }
+        let defaults: Vec<RenderRule> =
+            serde_json::from_str(DEFAULT_RULES).context("could not parse default render rules")?;
+        rules.extend(defaults);
+
+        for rule in &rules {
+            rule.validate()?;
+        }
+        Ok(Renderer { rules })
+    }
+
+    /// Writes the opening markup for an element to `output` using the first rule that matches
+    /// it, and returns the markup to close it with, or None if no rule matches.
+    pub fn render(
+        &self,
+        element: &str,
+        attrs: &[(String, String)],
+        empty: bool,
+        output: &mut String,
+    ) -> Option<String> {
+        self.rules
+            .iter()
+            .find(|rule| rule.matches(element, attrs, empty))
+            .map(|rule| rule.open(attrs, output))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attrs(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn self_closing_bibl_has_no_text_by_default() {
+        let renderer = Renderer::load(None).unwrap();
+        let bibl = attrs(&[
+            ("n", "Perseus:abo:tlg,0012,001:1:1"),
+            ("urn", "urn:cts:greekLit:tlg0012.tlg001:1.1"),
+            ("passage", "1.1"),
+        ]);
+        let mut output = String::new();
+        let close = renderer.render("bibl", &bibl, true, &mut output).unwrap();
+        output.push_str(&close);
+        assert_eq!(
+            output,
+            r#"<a class="bi" biblink="Perseus:abo:tlg,0012,001:1:1" data-urn="urn:cts:greekLit:tlg0012.tlg001:1.1"></a>"#
+        );
+
+        // a lexicon's own rule comes first
+        let dir = tempfile::tempdir().unwrap();
+        let rules = dir.path().join("rules.json");
+        fs::write(
+            &rules,
+            r#"[{ "element": "bibl", "empty": true, "output": "a", "text": "{passage}" }]"#,
+        )
+        .unwrap();
+        let renderer = Renderer::load(Some(&rules)).unwrap();
+        let mut output = String::new();
+        let close = renderer.render("bibl", &bibl, true, &mut output).unwrap();
+        output.push_str(&close);
+        assert_eq!(output, "<a>1.1</a>");
+    }
+
+    #[test]
+    fn page_break_text_is_escaped() {
+        let renderer = Renderer::load(None).unwrap();
+        let mut output = String::new();
+        renderer.render("pb", &attrs(&[("n", "1<2")]), true, &mut output);
+        assert_eq!(output, r#"<span class="pb" data-page="1&lt;2">[p. 1&lt;2]"#);
+    }
+}
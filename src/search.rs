@@ -0,0 +1,351 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::Range;
+
+use anyhow::Context;
+use serde::Serialize;
+use tantivy::aggregation::agg_req::Aggregations;
+use tantivy::aggregation::agg_result::{AggregationResult, BucketResult};
+use tantivy::aggregation::{AggregationCollector, AggregationLimitsGuard, Key};
+use tantivy::collector::{Count, TopDocs};
+use tantivy::query::{BooleanQuery, FuzzyTermQuery, Occur, Query, QueryParser, TermQuery};
+use tantivy::schema::{Field, IndexRecordOption, Schema, TantivyDocument, Term, Value};
+use tantivy::snippet::SnippetGenerator;
+use tantivy::{Score, Searcher};
+
+use crate::analysis::{MAX_LEMMA_PREFIX, highlight_analyzer, normalize_lemma};
+
+// length of a snippet, in characters
+const SNIPPET_CHARS: usize = 150;
+
+/// How a search matches its query.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SearchMode {
+    /// a tantivy query on the definition (or any field named in it)
+    Query,
+    /// lemmas starting with the query, for type-ahead
+    Prefix,
+    /// lemmas close to the query, for "did you mean"
+    Fuzzy,
+}
+
+/// A search and the page of results wanted.
+pub struct SearchRequest<'a> {
+    pub query: &'a str,
+    pub mode: SearchMode,
+    /// lexica to search, or all of them if empty
+    pub lexica: &'a [String],
+    pub offset: usize,
+    pub limit: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchResults {
+    /// number of matching entries in the lexica searched
+    pub total: usize,
+    pub hits: Vec<Hit>,
+    /// number of matching entries in each lexicon, whichever lexica were searched
+    pub lexica: BTreeMap<String, u64>,
+}
+
+/// One matching entry.
+#[derive(Debug, Serialize)]
+pub struct Hit {
+    pub word_id: u64,
+    pub lemma: String,
+    pub lexicon: String,
+    pub score: f32,
+    /// plain text from the definition around the best match, or its start if no words of the
+    /// query are in the definition
+    pub snippet: String,
+    /// byte ranges of the snippet that match the query
+    pub highlights: Vec<Range<usize>>,
+}
+
+/// Runs a search and returns one page of its results.
+pub fn search(searcher: &Searcher, request: &SearchRequest) -> anyhow::Result<SearchResults> {
+    // tantivy panics on a page of no results
+    anyhow::ensure!(request.limit > 0, "the limit must be at least 1");
+    let index = searcher.index();
+    let schema = index.schema();
+    let word_id_field = schema_field(&schema, "word_id")?;
+    let lemma_field = schema_field(&schema, "lemma")?;
+    let lexicon_field = schema_field(&schema, "lexicon")?;
+    let definition_field = schema_field(&schema, "definition")?;
+    let text_la_field = schema_field(&schema, "text_la")?;
+
+    let query = match request.mode {
+        SearchMode::Query => {
+            let query_parser = QueryParser::for_index(
+                index,
+                //this vector contains default fields used if field is not specified in query
+                // (text_la for words in Latin spans, which the definition has only in Latin form)
+                vec![lexicon_field, definition_field, text_la_field],
+            );
+            query_parser
+                .parse_query(request.query)
+                .with_context(|| format!("could not parse query {}", request.query))?
+        }
+        SearchMode::Prefix | SearchMode::Fuzzy => {
+            lemma_query(&schema, request.query, request.mode)?
+        }
+    };
+
+    let lexica = lexicon_counts(searcher, &*query)?;
+
+    let filtered: Box<dyn Query> = if request.lexica.is_empty() {
+        query.box_clone()
+    } else {
+        let lexicon_queries = request
+            .lexica
+            .iter()
+            .map(|name| -> Box<dyn Query> {
+                Box::new(TermQuery::new(
+                    Term::from_field_text(lexicon_field, name),
+                    IndexRecordOption::Basic,
+                ))
+            })
+            .collect();
+        Box::new(BooleanQuery::new(vec![
+            (Occur::Must, query.box_clone()),
+            (Occur::Must, Box::new(BooleanQuery::union(lexicon_queries))),
+        ]))
+    };
+    let (top_docs, total) = searcher.search(
+        &*filtered,
+        &(
+            TopDocs::with_limit(request.limit).and_offset(request.offset),
+            Count,
+        ),
+    )?;
+
+    // the definition is highlighted with the terms of the query on it and on text_la, whose
+    // words are in the definition too; highlight_analyzer gives each word both its forms
+    let terms = highlight_terms(searcher, &*query, &[definition_field, text_la_field])?;
+    let snippets =
+        SnippetGenerator::new(terms, highlight_analyzer(), definition_field, SNIPPET_CHARS);
+    let mut hits = Vec::new();
+    for (score, doc_address) in top_docs {
+        let doc: TantivyDocument = searcher.doc(doc_address)?;
+        let text = |field| {
+            doc.get_first(field)
+                .and_then(|v| v.as_str())
+                .unwrap_or_default()
+                .to_string()
+        };
+        let definition = text(definition_field);
+        let snippet = snippets.snippet(&definition);
+        let (snippet, highlights) = if snippet.highlighted().is_empty() {
+            (definition.chars().take(SNIPPET_CHARS).collect(), Vec::new())
+        } else {
+            (
+                snippet.fragment().to_string(),
+                merge_ranges(snippet.highlighted().to_vec()),
+            )
+        };
+        hits.push(Hit {
+            word_id: doc
+                .get_first(word_id_field)
+                .and_then(|v| v.as_u64())
+                .unwrap_or_default(),
+            lemma: text(lemma_field),
+            lexicon: text(lexicon_field),
+            score,
+            snippet,
+            highlights,
+        });
+    }
+
+    Ok(SearchResults {
+        total,
+        hits,
+        lexica,
+    })
+}
+
+// sorts byte ranges and merges those that overlap: a word matched in both its English and its
+// Latin form is highlighted twice over the same bytes
+fn merge_ranges(mut ranges: Vec<Range<usize>>) -> Vec<Range<usize>> {
+    ranges.sort_by_key(|range| (range.start, range.end));
+    let mut merged: Vec<Range<usize>> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if range.start < last.end => last.end = last.end.max(range.end),
+            _ => merged.push(range),
+        }
+    }
+    merged
+}
+
+// a field of the index schema, which an index built by an older version may lack
+fn schema_field(schema: &Schema, name: &str) -> anyhow::Result<Field> {
+    schema
+        .get_field(name)
+        .with_context(|| format!("the index has no {name} field; rebuild the index"))
+}
+
+// the terms of the query on any of the fields, scored as SnippetGenerator::create does
+fn highlight_terms(
+    searcher: &Searcher,
+    query: &dyn Query,
+    fields: &[Field],
+) -> anyhow::Result<BTreeMap<String, Score>> {
+    let mut terms = BTreeSet::new();
+    query.query_terms(&mut |term, _| {
+        if fields.contains(&term.field()) {
+            terms.insert(term.clone());
+        }
+    });
+    let mut terms_text = BTreeMap::new();
+    for term in terms {
+        let Some(text) = term.value().as_str().map(str::to_string) else {
+            continue;
+        };
+        let doc_freq = searcher.doc_freq(&term)?;
+        if doc_freq > 0 {
+            let score = 1.0 / (1.0 + doc_freq as Score);
+            // a word in both fields keeps its better score
+            let best = terms_text.entry(text).or_insert(score);
+            *best = best.max(score);
+        }
+    }
+    Ok(terms_text)
+}
+
+// a type-ahead (prefix) or "did you mean" (fuzzy) lookup of a lemma, ignoring diacritics
+fn lemma_query(schema: &Schema, lemma: &str, mode: SearchMode) -> anyhow::Result<Box<dyn Query>> {
+    let lemma = normalize_lemma(lemma);
+    if mode == SearchMode::Prefix {
+        let field = schema_field(schema, "lemma_prefix")?;
+        // prefixes are only indexed up to MAX_LEMMA_PREFIX characters
+        let prefix: String = lemma.chars().take(MAX_LEMMA_PREFIX).collect();
+        let term = Term::from_field_text(field, &prefix);
+        return Ok(Box::new(TermQuery::new(term, IndexRecordOption::Basic)));
+    }
+    let field = schema_field(schema, "lemma_folded")?;
+    // one edit for short lemmas, where two would match almost anything
+    let distance = if lemma.chars().count() < 5 { 1 } else { 2 };
+    Ok(Box::new(FuzzyTermQuery::new(
+        Term::from_field_text(field, &lemma),
+        distance,
+        true,
+    )))
+}
+
+// the number of entries matching the query in each lexicon
+fn lexicon_counts(searcher: &Searcher, query: &dyn Query) -> anyhow::Result<BTreeMap<String, u64>> {
+    let aggregations: Aggregations = serde_json::from_value(serde_json::json!({
+        "lexica": { "terms": { "field": "lexicon", "size": 1000 } }
+    }))?;
+    let collector =
+        AggregationCollector::from_aggs(aggregations, AggregationLimitsGuard::default());
+    let mut results = searcher.search(query, &collector)?;
+
+    let mut counts = BTreeMap::new();
+    if let Some(AggregationResult::BucketResult(BucketResult::Terms { buckets, .. })) =
+        results.0.remove("lexica")
+    {
+        for bucket in buckets {
+            if let Key::Str(lexicon) = bucket.key {
+                counts.insert(lexicon, bucket.doc_count);
+            }
+        }
+    }
+    Ok(counts)
+}
+
+#[cfg(test)]
+mod tests {
+    use tantivy::{Index, IndexWriter};
+
+    use super::*;
+    use crate::analysis::tokenize_definition;
+    use crate::{IndexFields, build_schema, register_tokenizers};
+
+    // an index of (lemma, definition, byte range of a Latin span in it) entries of one lexicon
+    fn test_index(entries: &[(&str, &str, Option<Range<usize>>)]) -> Index {
+        let index = Index::create_in_ram(build_schema(true));
+        register_tokenizers(&index);
+        let fields = IndexFields::new(&index.schema()).unwrap();
+        let mut writer: IndexWriter = index.writer(15_000_000).unwrap();
+        for (word_id, (lemma, definition, latin)) in entries.iter().enumerate() {
+            let mut doc = TantivyDocument::default();
+            doc.add_u64(fields.word_id, word_id as u64 + 1);
+            doc.add_text(fields.lemma, lemma);
+            doc.add_text(fields.lemma_folded, lemma);
+            doc.add_text(fields.lemma_prefix, lemma);
+            doc.add_text(fields.lexicon, "test");
+            let latin_spans: Vec<Range<usize>> = latin.iter().cloned().collect();
+            doc.add_pre_tokenized_text(
+                fields.definition,
+                tokenize_definition(definition, &latin_spans),
+            );
+            for span in &latin_spans {
+                // parts[2] is text_la
+                doc.add_text(fields.parts[2], &definition[span.clone()]);
+            }
+            writer.add_document(doc).unwrap();
+        }
+        writer.commit().unwrap();
+        index
+    }
+
+    fn search_for(index: &Index, query: &str, mode: SearchMode) -> SearchResults {
+        let searcher = index.reader().unwrap().searcher();
+        let request = SearchRequest {
+            query,
+            mode,
+            lexica: &[],
+            offset: 0,
+            limit: 10,
+        };
+        search(&searcher, &request).unwrap()
+    }
+
+    #[test]
+    fn a_word_matched_in_both_forms_is_highlighted_once() {
+        // "virtus" is indexed as English in the second entry and as Latin in the first, so the
+        // query has a term on definition and one on text_la, and both match the first entry's
+        // words, which are highlighted in both forms
+        let index = test_index(&[
+            ("uirtus", "manliness, virtus", Some(11..17)),
+            ("uir", "man, whence virtus", None),
+        ]);
+        let results = search_for(&index, "virtus", SearchMode::Query);
+        assert_eq!(results.total, 2);
+        for hit in &results.hits {
+            assert_eq!(hit.highlights.len(), 1, "{hit:?}");
+            assert_eq!(&hit.snippet[hit.highlights[0].clone()], "virtus");
+        }
+    }
+
+    #[test]
+    fn lemmas_by_prefix_and_fuzzily() {
+        let index = test_index(&[("λύω", "loose", None), ("λέγω", "say", None)]);
+        let lemmas = |results: SearchResults| {
+            results
+                .hits
+                .into_iter()
+                .map(|hit| hit.lemma)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            lemmas(search_for(&index, "λύ", SearchMode::Prefix)),
+            ["λύω"]
+        );
+        assert_eq!(
+            lemmas(search_for(&index, "λύο", SearchMode::Fuzzy)),
+            ["λύω"]
+        );
+        let results = search_for(&index, "say", SearchMode::Query);
+        assert_eq!(lemmas(results), ["λέγω"]);
+    }
+
+    #[test]
+    fn ranges_are_sorted_and_merged() {
+        assert_eq!(
+            merge_ranges(vec![5..8, 0..3, 5..8, 2..4, 10..12]),
+            [0..4, 5..8, 10..12]
+        );
+        assert!(merge_ranges(Vec::new()).is_empty());
+    }
+}
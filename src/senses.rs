@@ -0,0 +1,219 @@
+use serde::Serialize;
+
+/// One sense of an entry, with the senses below it.
+#[derive(Debug, Serialize)]
+pub struct Sense {
+    pub level: u32,
+    pub n: String,
+    /// labels of this sense and its ancestors joined by ".", e.g. "A.II.2.b"
+    pub path: String,
+    pub text: String,
+    pub citations: Vec<String>,
+    pub senses: Vec<Sense>,
+}
+
+struct OpenSense {
+    sense: Sense,
+    in_element: bool, // still inside its <sense> element
+}
+
+/// Builds the sense tree of an entry from its `<sense>` elements.
+///
+/// Senses may be nested in the xml or, as in LSJ, written as siblings whose hierarchy is given by
+/// their `level` attribute; both end up as the same tree.
+#[derive(Default)]
+pub struct SenseTreeBuilder {
+    roots: Vec<Sense>,
+    stack: Vec<OpenSense>,
+}
+
+impl SenseTreeBuilder {
+    pub fn clear(&mut self) {
+        self.roots.clear();
+        self.stack.clear();
+    }
+
+    pub fn start(&mut self, level: Option<u32>, n: &str) {
+        let open_elements = self.stack.iter().filter(|s| s.in_element).count() as u32;
+        let level = level.unwrap_or(open_elements + 1);
+
+        // close finished senses at the same or a deeper level
+        while self
+            .stack
+            .last()
+            .is_some_and(|top| !top.in_element && top.sense.level >= level)
+        {
+            self.pop();
+        }
+
+        let parent_path = self.stack.last().map_or("", |top| top.sense.path.as_str());
+        let path = match (parent_path.is_empty(), n.is_empty()) {
+            (_, true) => parent_path.to_string(),
+            (true, false) => n.to_string(),
+            (false, false) => format!("{}.{}", parent_path, n),
+        };
+        self.stack.push(OpenSense {
+            sense: Sense {
+                level,
+                n: n.to_string(),
+                path,
+                text: String::new(),
+                citations: Vec::new(),
+                senses: Vec::new(),
+            },
+            in_element: true,
+        });
+    }
+
+    pub fn end(&mut self) {
+        if let Some(open) = self.stack.iter_mut().rev().find(|s| s.in_element) {
+            open.in_element = false;
+        }
+    }
+
+    /// Path of the innermost sense whose element is still open, or "" if there is none.
+    pub fn current_path(&self) -> &str {
+        self.stack
+            .iter()
+            .rev()
+            .find(|s| s.in_element)
+            .map_or("", |s| s.sense.path.as_str())
+    }
+
+    /// Adds text to the innermost sense whose element is still open.
+    pub fn push_text(&mut self, text: &str) {
+        if let Some(open) = self.stack.iter_mut().rev().find(|s| s.in_element) {
+            open.sense.text.push_str(text);
+        }
+    }
+
+    pub fn add_citation(&mut self, citation: &str) {
+        if let Some(open) = self.stack.iter_mut().rev().find(|s| s.in_element) {
+            open.sense.citations.push(citation.to_string());
+        }
+    }
+
+    fn pop(&mut self) {
+        if let Some(mut open) = self.stack.pop() {
+            open.sense.text = open
+                .sense
+                .text
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" ");
+            match self.stack.last_mut() {
+                Some(parent) => parent.sense.senses.push(open.sense),
+                None => self.roots.push(open.sense),
+            }
+        }
+    }
+
+    /// Closes any remaining senses and returns the top level senses of the entry.
+    pub fn finish(&mut self) -> Vec<Sense> {
+        while !self.stack.is_empty() {
+            self.pop();
+        }
+        std::mem::take(&mut self.roots)
+    }
+}
+
+/// Lists every sense in the tree, parents before their children.
+pub fn flatten(senses: &[Sense]) -> Vec<&Sense> {
+    let mut all = Vec::new();
+    for sense in senses {
+        all.push(sense);
+        all.extend(flatten(&sense.senses));
+    }
+    all
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // each sense's path, level and text, parents before their children
+    fn outline(senses: &[Sense]) -> Vec<(String, u32, String)> {
+        flatten(senses)
+            .iter()
+            .map(|s| (s.path.clone(), s.level, s.text.clone()))
+            .collect()
+    }
+
+    fn expected() -> Vec<(String, u32, String)> {
+        [
+            ("A", 1, "loose"),
+            ("A.I", 2, "untie"),
+            ("A.I.1", 3, "of knots"),
+            ("A.II", 2, "release"),
+            ("B", 1, "pay"),
+        ]
+        .iter()
+        .map(|(path, level, text)| (path.to_string(), *level, text.to_string()))
+        .collect()
+    }
+
+    #[test]
+    fn sibling_senses_nest_by_level() {
+        // as in LSJ, where each <sense> is closed before the next and level gives the depth
+        let mut builder = SenseTreeBuilder::default();
+        for (level, n, text) in [
+            (1, "A", "loose"),
+            (2, "I", "untie"),
+            (3, "1", " of \n knots "),
+            (2, "II", "release"),
+            (1, "B", "pay"),
+        ] {
+            builder.start(Some(level), n);
+            builder.push_text(text);
+            builder.end();
+        }
+        let senses = builder.finish();
+        assert_eq!(senses.len(), 2);
+        assert_eq!(outline(&senses), expected());
+    }
+
+    #[test]
+    fn nested_senses_take_their_level_from_their_depth() {
+        let mut builder = SenseTreeBuilder::default();
+        builder.start(None, "A");
+        builder.push_text("loose");
+        builder.start(None, "I");
+        builder.push_text("untie");
+        builder.start(None, "1");
+        assert_eq!(builder.current_path(), "A.I.1");
+        builder.push_text("of knots");
+        builder.add_citation("Il. 1.1");
+        builder.end();
+        builder.end();
+        builder.start(None, "II");
+        builder.push_text("release");
+        builder.end();
+        builder.end();
+        assert_eq!(builder.current_path(), "");
+        builder.start(None, "B");
+        builder.push_text("pay");
+        builder.end();
+        let senses = builder.finish();
+        assert_eq!(outline(&senses), expected());
+        assert_eq!(senses[0].senses[0].senses[0].citations, ["Il. 1.1"]);
+    }
+
+    #[test]
+    fn text_after_a_nested_sense_goes_to_its_parent() {
+        let mut builder = SenseTreeBuilder::default();
+        builder.start(Some(1), "A");
+        builder.push_text("loose");
+        builder.start(Some(2), "");
+        builder.push_text("untie");
+        builder.end();
+        builder.push_text(", undo");
+        builder.end();
+        let senses = builder.finish();
+        assert_eq!(senses[0].text, "loose, undo");
+        // a sense with no n has its parent's path
+        assert_eq!(senses[0].senses[0].path, "A");
+
+        builder.clear();
+        assert!(builder.finish().is_empty());
+    }
+}
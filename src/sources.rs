@@ -0,0 +1,358 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, bail};
+use git2::{Oid, Repository};
+
+use crate::config::{Lexicon, MissingFiles};
+
+/// Returns the lexicon's source files in load order.
+///
+/// A manifest is loaded in the order given. Glob matches are sorted by file name, comparing
+/// runs of digits numerically, so that `seq` values stay the same from one build to the next
+/// regardless of directory order or zero padding. A gap in numbered glob matches, such as a
+/// missing `greatscott03.xml` between `greatscott02.xml` and `greatscott04.xml`, counts as a
+/// missing file unless it is listed in `expected_gaps`.
+pub fn source_files(lex: &Lexicon) -> anyhow::Result<Vec<PathBuf>> {
+    let dir = Path::new(&lex.dir_name);
+    if !dir.is_dir() {
+        bail!(
+            "source directory {} for lexicon \"{}\" does not exist; run fetch first",
+            dir.display(),
+            lex.name
+        );
+    }
+
+    let mut files = Vec::new();
+    if let Some(manifest) = &lex.file_manifest {
+        for name in manifest {
+            let path = dir.join(name);
+            if path.is_file() {
+                files.push(path);
+            } else {
+                missing(
+                    lex,
+                    format!(
+                        "file {} listed in file_manifest does not exist",
+                        path.display()
+                    ),
+                )?;
+            }
+        }
+    } else if let Some(pattern) = &lex.file_glob {
+        let mut names = Vec::new();
+        for dir_entry in fs::read_dir(dir)
+            .with_context(|| format!("could not read source directory {}", dir.display()))?
+        {
+            let dir_entry = dir_entry?;
+            let name = dir_entry.file_name().to_string_lossy().to_string();
+            if dir_entry.file_type()?.is_file() && glob_match(pattern, &name) {
+                names.push(name);
+            }
+        }
+        // excluded files are still there, so they do not leave gaps
+        let mut gaps = numbering_gaps(&names);
+        gaps.retain(|name| !lex.expected_gaps.contains(name));
+        if !gaps.is_empty() {
+            missing(
+                lex,
+                format!(
+                    "{} missing from the numbered files in {}",
+                    gaps.join(", "),
+                    dir.display()
+                ),
+            )?;
+        }
+        names.retain(|name| !lex.file_exclude.contains(name));
+        names.sort_by(|a, b| natural_cmp(a, b));
+        if names.is_empty() {
+            missing(
+                lex,
+                format!("no files in {} match \"{}\"", dir.display(), pattern),
+            )?;
+        }
+        files.extend(names.iter().map(|name| dir.join(name)));
+    }
+    Ok(files)
+}
+
+fn missing(lex: &Lexicon, msg: String) -> anyhow::Result<()> {
+    match lex.missing_files {
+        MissingFiles::Error => bail!("lexicon \"{}\": {}", lex.name, msg),
+        MissingFiles::Warn => {
+            eprintln!("warning: lexicon \"{}\": {}", lex.name, msg);
+            Ok(())
+        }
+    }
+}
+
+/// Matches a file name against a pattern where `*` matches any run of characters and `?`
+/// matches exactly one.
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let p: Vec<char> = pattern.chars().collect();
+    let n: Vec<char> = name.chars().collect();
+    let (mut pi, mut ni) = (0, 0);
+    let mut star: Option<(usize, usize)> = None; // position of last * and the name position it matched up to
+
+    while ni < n.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == n[ni]) {
+            pi += 1;
+            ni += 1;
+        } else if pi < p.len() && p[pi] == '*' {
+            star = Some((pi, ni));
+            pi += 1;
+        } else if let Some((star_pi, star_ni)) = star {
+            // let the last * swallow one more character and try again
+            pi = star_pi + 1;
+            ni = star_ni + 1;
+            star = Some((star_pi, star_ni + 1));
+        } else {
+            return false;
+        }
+    }
+    p[pi..].iter().all(|c| *c == '*')
+}
+
+/// The file names missing from each numbered series of names, a series being names that differ
+/// only in their last run of digits, e.g. `a3.xml` given `a2.xml` and `a4.xml`. Missing names
+/// are zero padded like the first name of their series.
+fn numbering_gaps(names: &[String]) -> Vec<String> {
+    // numbers in each series, by the text before and after them
+    let mut series: BTreeMap<(&str, &str), (usize, BTreeSet<u64>)> = BTreeMap::new();
+    for name in names {
+        let Some(end) = name.rfind(|c: char| c.is_ascii_digit()).map(|i| i + 1) else {
+            continue;
+        };
+        let start = name[..end]
+            .rfind(|c: char| !c.is_ascii_digit())
+            .map_or(0, |i| i + 1);
+        let Ok(number) = name[start..end].parse() else {
+            continue;
+        };
+        let width = if name[start..].starts_with('0') {
+            end - start
+        } else {
+            0
+        };
+        series
+            .entry((&name[..start], &name[end..]))
+            .or_insert((width, BTreeSet::new()))
+            .1
+            .insert(number);
+    }
+
+    let mut gaps = Vec::new();
+    for ((prefix, suffix), (width, numbers)) in series {
+        let (Some(first), Some(last)) = (numbers.first(), numbers.last()) else {
+            continue;
+        };
+        for number in *first..*last {
+            if !numbers.contains(&number) {
+                gaps.push(format!("{prefix}{number:0width$}{suffix}"));
+            }
+        }
+    }
+    gaps
+}
+
+/// Compares strings treating runs of ascii digits as numbers, e.g. "a2" < "a10".
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut a = a.chars().peekable();
+    let mut b = b.chars().peekable();
+    loop {
+        match (a.peek(), b.peek()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let x_num = take_digits(&mut a);
+                let y_num = take_digits(&mut b);
+                let x_trimmed = x_num.trim_start_matches('0');
+                let y_trimmed = y_num.trim_start_matches('0');
+                let ord = x_trimmed
+                    .len()
+                    .cmp(&y_trimmed.len())
+                    .then_with(|| x_trimmed.cmp(y_trimmed))
+                    .then_with(|| x_num.len().cmp(&y_num.len()));
+                if ord != Ordering::Equal {
+                    return ord;
+                }
+            }
+            (Some(x), Some(y)) => {
+                let ord = x.cmp(y);
+                if ord != Ordering::Equal {
+                    return ord;
+                }
+                a.next();
+                b.next();
+            }
+        }
+    }
+}
+
+fn take_digits(chars: &mut std::iter::Peekable<std::str::Chars>) -> String {
+    let mut digits = String::new();
+    while let Some(c) = chars.next_if(|c| c.is_ascii_digit()) {
+        digits.push(c);
+    }
+    digits
+}
+
+/// The byte offset of the start of each line of a file, for finding the line an offset is on.
+pub struct LineIndex {
+    bytes: Vec<u8>,
+    line_starts: Vec<u64>,
+}
+
+impl LineIndex {
+    pub fn read(file: &Path) -> std::io::Result<LineIndex> {
+        let bytes = fs::read(file)?;
+        let mut line_starts = vec![0];
+        for (pos, b) in bytes.iter().enumerate() {
+            if *b == b'\n' {
+                line_starts.push(pos as u64 + 1);
+            }
+        }
+        Ok(LineIndex { bytes, line_starts })
+    }
+
+    /// 1-based line number of a byte offset
+    pub fn line(&self, offset: u64) -> usize {
+        self.line_starts.partition_point(|start| *start <= offset)
+    }
+
+    /// 1-based line and column (in characters) of a byte offset
+    pub fn line_col(&self, offset: u64) -> (usize, usize) {
+        let offset = offset.min(self.bytes.len() as u64);
+        let line = self.line(offset);
+        let line_start = self.line_starts[line - 1] as usize;
+        let column = String::from_utf8_lossy(&self.bytes[line_start..offset as usize])
+            .chars()
+            .count()
+            + 1;
+        (line, column)
+    }
+}
+
+/// The files in the lexicon's directory that were added, changed or deleted between two commits
+/// of its repository, as paths relative to the directory, or None if they cannot be worked out,
+/// e.g. because the old commit is no longer in the repository.
+pub fn changed_files(lex: &Lexicon, from: &str, to: &str) -> Option<BTreeSet<String>> {
+    let repo = Repository::discover(&lex.dir_name).ok()?;
+    let tree = |commit: &str| {
+        let oid = Oid::from_str(commit).ok()?;
+        repo.find_commit(oid).ok()?.tree().ok()
+    };
+    let diff = repo
+        .diff_tree_to_tree(Some(&tree(from)?), Some(&tree(to)?), None)
+        .ok()?;
+
+    // diff paths are relative to the top of the repository, which may be above the lexicon's directory
+    let top = repo.workdir()?.canonicalize().ok()?;
+    let dir = Path::new(&lex.dir_name).canonicalize().ok()?;
+    let prefix = dir.strip_prefix(&top).ok()?;
+
+    let mut files = BTreeSet::new();
+    for delta in diff.deltas() {
+        for file in [delta.old_file(), delta.new_file()] {
+            if let Some(path) = file.path()
+                && let Ok(path) = path.strip_prefix(prefix)
+            {
+                files.insert(path.to_string_lossy().to_string());
+            }
+        }
+    }
+    Some(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_col_counts_characters() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("a.xml");
+        fs::write(&file, "<a>\nλύω <b>\n").unwrap();
+        let lines = LineIndex::read(&file).unwrap();
+        assert_eq!(lines.line_col(0), (1, 1));
+        assert_eq!(lines.line_col(4), (2, 1));
+        // λύω is 6 bytes but 3 characters
+        assert_eq!(lines.line_col(11), (2, 5));
+        assert_eq!(lines.line_col(100), (3, 1));
+    }
+
+    #[test]
+    fn glob_wildcards() {
+        assert!(glob_match("greatscott*.xml", "greatscott02.xml"));
+        assert!(glob_match("greatscott*.xml", "greatscott.xml"));
+        assert!(!glob_match("greatscott*.xml", "greatscott02.xml.bak"));
+        assert!(glob_match("a?.xml", "a1.xml"));
+        assert!(!glob_match("a?.xml", "a.xml"));
+        assert!(!glob_match("a?.xml", "a12.xml"));
+        // the star backtracks past a false match of what follows it
+        assert!(glob_match("*a*b.xml", "xaab_ab.xml"));
+        assert!(glob_match("*", ""));
+        assert!(!glob_match("", "a"));
+        // ? is one character, not one byte
+        assert!(glob_match("?.xml", "λ.xml"));
+    }
+
+    #[test]
+    fn numbers_compare_numerically() {
+        assert_eq!(natural_cmp("a2.xml", "a10.xml"), Ordering::Less);
+        assert_eq!(natural_cmp("a10.xml", "a9.xml"), Ordering::Greater);
+        assert_eq!(natural_cmp("a02.xml", "a10.xml"), Ordering::Less);
+        // zero padding only breaks ties
+        assert_eq!(natural_cmp("a2.xml", "a02.xml"), Ordering::Less);
+        assert_eq!(natural_cmp("a2.xml", "a2.xml"), Ordering::Equal);
+        assert_eq!(natural_cmp("a2", "a2b"), Ordering::Less);
+        assert_eq!(natural_cmp("b1", "a2"), Ordering::Greater);
+        // numbers longer than any integer type
+        assert_eq!(
+            natural_cmp("a99999999999999999999999", "a100000000000000000000000"),
+            Ordering::Less
+        );
+    }
+
+    #[test]
+    fn gaps_in_numbered_files() {
+        let names = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            numbering_gaps(&names(&["g01.xml", "g02.xml", "g05.xml", "g04.xml"])),
+            ["g03.xml"]
+        );
+        assert_eq!(
+            numbering_gaps(&names(&["d9.xml", "d12.xml", "x.xml"])),
+            ["d10.xml", "d11.xml"]
+        );
+        // each series is checked on its own
+        assert!(numbering_gaps(&names(&["a1.xml", "b5.xml", "a2.xml", "a1.txt"])).is_empty());
+        // only the last number of a name counts
+        assert!(numbering_gaps(&names(&["v1-1.xml", "v3-2.xml"])).is_empty());
+    }
+
+    #[test]
+    fn shipped_registry_resolves() {
+        let registry = Path::new(env!("CARGO_MANIFEST_DIR")).join(crate::config::DEFAULT_REGISTRY);
+        let lexica = crate::config::load_lexica(&registry).unwrap();
+        // the files in each enabled lexicon's repository
+        let repositories = [
+            ("lsj", "greatscott", 1..=86, None),
+            ("lewisshort", "latindico", 1..=25, Some(10)),
+        ];
+        for (name, stem, numbers, absent) in repositories {
+            let mut lex = lexica.iter().find(|l| l.name == name).unwrap().clone();
+            let dir = tempfile::tempdir().unwrap();
+            lex.dir_name = dir.path().to_string_lossy().into_owned();
+            for i in numbers.clone().filter(|i| Some(*i) != absent) {
+                fs::write(dir.path().join(format!("{stem}{i:02}.xml")), "").unwrap();
+            }
+            let files = source_files(&lex).unwrap();
+            let expected = numbers.count() - usize::from(absent.is_some()) - lex.file_exclude.len();
+            assert_eq!(files.len(), expected, "{name}");
+        }
+    }
+}
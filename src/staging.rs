@@ -0,0 +1,267 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use tempfile::TempDir;
+
+/// A db file or index directory built in a temporary directory beside the live one, to be moved
+/// into place once the whole build has succeeded. If it is dropped first, it is deleted.
+pub struct StagedPath {
+    dir: TempDir,
+    live: PathBuf,
+}
+
+impl StagedPath {
+    pub fn new(live: &Path) -> anyhow::Result<StagedPath> {
+        // in the same directory as the live path, so that it can be renamed into place
+        let parent = match live.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        let dir = tempfile::Builder::new()
+            .prefix(".build-")
+            .tempdir_in(parent)
+            .with_context(|| {
+                format!("could not create a build directory in {}", parent.display())
+            })?;
+        Ok(StagedPath {
+            dir,
+            live: live.to_path_buf(),
+        })
+    }
+
+    /// The db or index the build replaces.
+    pub fn live(&self) -> &Path {
+        &self.live
+    }
+
+    /// Copies the live db file or index directory to the build path, for a build that updates
+    /// it rather than starting again. Returns false if there is no live one.
+    pub fn copy_live(&self) -> anyhow::Result<bool> {
+        let to = self.path();
+        if self.live.is_file() {
+            fs::copy(&self.live, &to)?;
+        } else if self.live.is_dir() {
+            fs::create_dir(&to)?;
+            for entry in fs::read_dir(&self.live)? {
+                let entry = entry?;
+                let name = entry.file_name();
+                // an index directory is flat; skip the lock files of any writer using it
+                if entry.file_type()?.is_file() && !name.to_string_lossy().ends_with(".lock") {
+                    fs::copy(entry.path(), to.join(name))?;
+                }
+            }
+        } else {
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    /// Where the new build is written.
+    pub fn path(&self) -> PathBuf {
+        self.dir
+            .path()
+            .join(self.live.file_name().unwrap_or("build".as_ref()))
+    }
+}
+
+/// Where the previous build is kept after a new one replaces it, e.g. dbv3.sqlite.prev.
+pub fn backup_path(live: &Path) -> PathBuf {
+    with_suffix(live, ".prev")
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+/// Moves the new builds into place, keeping the live ones as backups. A db file is replaced in a
+/// single rename, so it is never missing, its backup being a link to (or copy of) the old file
+/// made beforehand. A directory cannot be renamed over another, so a live index is moved aside
+/// just before the new one goes in, and is missing in between. If a build cannot be moved in,
+/// the ones already moved are taken out again and the backups put back.
+pub fn publish(staged: &[StagedPath]) -> anyhow::Result<()> {
+    for s in staged {
+        let backup = backup_path(&s.live);
+        remove(&backup)?;
+        if s.live.is_file() {
+            link_or_copy(&s.live, &backup).with_context(|| {
+                format!(
+                    "could not keep {} as {}",
+                    s.live.display(),
+                    backup.display()
+                )
+            })?;
+        }
+    }
+    for (i, s) in staged.iter().enumerate() {
+        if let Err(e) = replace(s) {
+            for done in staged[..i].iter().rev() {
+                let _ = restore(done);
+            }
+            return Err(e).with_context(|| format!("could not move the new {}", s.live.display()));
+        }
+    }
+    Ok(())
+}
+
+// moves a new build over the live path, which publish has already backed up if it is a file
+fn replace(s: &StagedPath) -> std::io::Result<()> {
+    let backup = backup_path(&s.live);
+    if s.live.is_dir() {
+        fs::rename(&s.live, &backup)?;
+    }
+    fs::rename(s.path(), &s.live).inspect_err(|_| {
+        if backup.is_dir() {
+            let _ = fs::rename(&backup, &s.live);
+        }
+    })
+}
+
+// takes a published build out again, putting back the live path it replaced, if there was one
+fn restore(s: &StagedPath) -> std::io::Result<()> {
+    let backup = backup_path(&s.live);
+    if backup.is_dir() {
+        fs::rename(&s.live, s.path())?;
+        fs::rename(&backup, &s.live)
+    } else if backup.is_file() {
+        fs::rename(&backup, &s.live)
+    } else {
+        fs::rename(&s.live, s.path())
+    }
+}
+
+fn link_or_copy(from: &Path, to: &Path) -> std::io::Result<()> {
+    fs::hard_link(from, to).or_else(|_| fs::copy(from, to).map(|_| ()))
+}
+
+/// Swaps the live db or index with its backup from the previous build. A db file is swapped
+/// without ever being missing, as in publish.
+pub fn rollback(live: &Path) -> anyhow::Result<()> {
+    let backup = backup_path(live);
+    if !backup.exists() {
+        anyhow::bail!(
+            "there is no previous build of {} to roll back to",
+            live.display()
+        );
+    }
+    let swap = with_suffix(live, ".rollback");
+    remove(&swap)?;
+    if live.is_file() {
+        link_or_copy(live, &swap)?;
+    } else if live.exists() {
+        fs::rename(live, &swap)?;
+    }
+    fs::rename(&backup, live)?;
+    if swap.exists() {
+        fs::rename(&swap, &backup)?;
+    }
+    Ok(())
+}
+
+fn remove(path: &Path) -> anyhow::Result<()> {
+    if path.is_dir() {
+        fs::remove_dir_all(path)?;
+    } else if path.exists() {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a staged db file and index directory for the live ones in dir, holding the given text
+    fn stage(dir: &Path, text: &str) -> Vec<StagedPath> {
+        let db = StagedPath::new(&dir.join("db.sqlite")).unwrap();
+        fs::write(db.path(), text).unwrap();
+        let index = StagedPath::new(&dir.join("index")).unwrap();
+        fs::create_dir(index.path()).unwrap();
+        fs::write(index.path().join("meta.json"), text).unwrap();
+        vec![db, index]
+    }
+
+    // the text of the db file and index at live, or None where they are missing
+    fn texts(live: &Path) -> (Option<String>, Option<String>) {
+        (
+            fs::read_to_string(live.join("db.sqlite")).ok(),
+            fs::read_to_string(live.join("index/meta.json")).ok(),
+        )
+    }
+
+    fn both(text: &str) -> (Option<String>, Option<String>) {
+        (Some(text.to_string()), Some(text.to_string()))
+    }
+
+    #[test]
+    fn publish_keeps_the_previous_build() {
+        let dir = tempfile::tempdir().unwrap();
+        let live = dir.path();
+        let backups = |name: &str| backup_path(&live.join(name));
+
+        publish(&stage(live, "first")).unwrap();
+        assert_eq!(texts(live), both("first"));
+        assert!(!backups("db.sqlite").exists() && !backups("index").exists());
+
+        publish(&stage(live, "second")).unwrap();
+        assert_eq!(texts(live), both("second"));
+        assert_eq!(fs::read_to_string(backups("db.sqlite")).unwrap(), "first");
+        assert_eq!(
+            fs::read_to_string(backups("index").join("meta.json")).unwrap(),
+            "first"
+        );
+
+        // only the last build is kept
+        publish(&stage(live, "third")).unwrap();
+        assert_eq!(fs::read_to_string(backups("db.sqlite")).unwrap(), "second");
+
+        // the build directories are removed with the staged paths
+        let leftovers = fs::read_dir(live)
+            .unwrap()
+            .filter(|e| {
+                e.as_ref()
+                    .unwrap()
+                    .file_name()
+                    .to_string_lossy()
+                    .starts_with(".build-")
+            })
+            .count();
+        assert_eq!(leftovers, 0);
+    }
+
+    #[test]
+    fn failed_publish_puts_the_live_builds_back() {
+        for failing in [0, 1] {
+            let dir = tempfile::tempdir().unwrap();
+            let live = dir.path();
+            publish(&stage(live, "old")).unwrap();
+
+            let staged = stage(live, "new");
+            remove(&staged[failing].path()).unwrap();
+            assert!(publish(&staged).is_err());
+            assert_eq!(texts(live), both("old"), "failing {failing}");
+        }
+    }
+
+    #[test]
+    fn rollback_swaps_with_the_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        let live = dir.path();
+        assert!(rollback(&live.join("db.sqlite")).is_err());
+
+        publish(&stage(live, "old")).unwrap();
+        publish(&stage(live, "new")).unwrap();
+        for path in ["db.sqlite", "index"] {
+            rollback(&live.join(path)).unwrap();
+        }
+        assert_eq!(texts(live), both("old"));
+        // rolling back again undoes the rollback
+        for path in ["db.sqlite", "index"] {
+            rollback(&live.join(path)).unwrap();
+        }
+        assert_eq!(texts(live), both("new"));
+        assert!(!with_suffix(&live.join("db.sqlite"), ".rollback").exists());
+    }
+}